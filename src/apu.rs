@@ -1,5 +1,7 @@
 use crate::{audio, gameboy::CPU_CLOCK_HZ};
+use blip::{Blip, PHASES};
 
+mod blip;
 mod channel1;
 mod channel2;
 mod channel3;
@@ -17,6 +19,9 @@ pub const SAMPLES: usize = 512;
 
 trait Channel {
     fn emulate_t_cycle(&mut self);
+    // digital 0 is 1.0 and 15 is -1.0, the DAC inverts
+    // https://gbdev.io/pandocs/Audio_details.html#dacs
+    // a disabled channel outputs digital 0 to its DAC, only a disabled DAC is silent
     fn dac_output(&self) -> f32;
     fn read_nrxx(&self, x: u16) -> u8;
     fn write_nrxx(&mut self, x: u16, val: u8);
@@ -27,6 +32,16 @@ pub struct Apu {
     nr50: u8,
     nr51: u8,
     cycles: u128,
+    sample_phase: u128, // SAMPLE_RATE per cycle, a sample is taken every CPU_CLOCK_HZ
+    // high-pass filter, the capacitor in series with each output
+    capacitors: [f32; 2],
+    // a capacitor per channel, so that the stems are filtered like the mix
+    stem_capacitors: [f32; 4],
+    charge_factor: f32,
+    // band-limited left and right output
+    blips: [Blip; 2],
+    // band-limited output of each channel, while recording stems
+    stem_blips: [Blip; 4],
     fs: u8,
     channel1: channel1::Channel1,
     channel2: channel2::Channel2,
@@ -34,16 +49,33 @@ pub struct Apu {
     channel4: channel4::Channel4,
     samples: Box<[f32; SAMPLES * 2]>,
     sample_idx: usize,
-    audio: audio::Audio,
+    audio: Option<audio::Audio>, // None: headless
+    // output kept for recording, interleaved stereo
+    recording: Option<Vec<f32>>,
+    // output of each channel kept for recording, 4 channels interleaved, high-pass filtered
+    stems: Option<Vec<f32>>,
+    // host mix only, invisible to the game: bit n for channel n + 1
+    muted: u8,
+    solo: u8,
 }
 
 impl Apu {
-    pub fn new(audio: audio::Audio) -> Self {
+    // cgb: CGB hardware, its output capacitors charge faster
+    pub fn new(audio: Option<audio::Audio>, cgb: bool) -> Self {
+        // charge kept per cycle, measured on hardware
+        // https://gbdev.io/pandocs/Audio_details.html#obscure-behavior
+        let charge_factor: f32 = if cgb { 0.998943 } else { 0.999958 };
         Self {
             enabled: false,
             nr50: 0,
             nr51: 0,
             cycles: 0,
+            sample_phase: 0,
+            capacitors: [0.0; 2],
+            stem_capacitors: [0.0; 4],
+            charge_factor: charge_factor.powf(CPU_CLOCK_HZ as f32 / SAMPLE_RATE as f32),
+            blips: Default::default(),
+            stem_blips: Default::default(),
             fs: 0,
             channel1: channel1::Channel1::default(),
             channel2: channel2::Channel2::default(),
@@ -52,11 +84,16 @@ impl Apu {
             samples: Box::new([0.0; SAMPLES * 2]),
             sample_idx: 0,
             audio,
+            recording: None,
+            stems: None,
+            muted: 0,
+            solo: 0,
         }
     }
 
-    pub fn emulate_cycle(&mut self) {
-        for _ in 0..4 {
+    // t_cycles: 4 per CPU M-cycle, 2 in CGB double speed mode
+    pub fn emulate_cycle(&mut self, t_cycles: u8) {
+        for _ in 0..t_cycles {
             self.channel1.emulate_t_cycle();
             self.channel2.emulate_t_cycle();
             self.channel3.emulate_t_cycle();
@@ -71,32 +108,146 @@ impl Apu {
                 self.cycles = 0;
                 self.fs = (self.fs + 1) & 7;
             }
+            self.cycles += 1;
 
-            if self.cycles % (CPU_CLOCK_HZ / SAMPLE_RATE) == 0 {
-                let left_sample = ((((self.nr51 >> 7) & 0b1) as f32) * self.channel4.dac_output()
-                    + (((self.nr51 >> 6) & 0b1) as f32) * self.channel3.dac_output()
-                    + (((self.nr51 >> 5) & 0b1) as f32) * self.channel2.dac_output()
-                    + (((self.nr51 >> 4) & 0b1) as f32) * self.channel1.dac_output())
-                    / 4.0;
-                let right_sample = ((((self.nr51 >> 3) & 0b1) as f32) * self.channel4.dac_output()
-                    + (((self.nr51 >> 2) & 0b1) as f32) * self.channel3.dac_output()
-                    + (((self.nr51 >> 1) & 0b1) as f32) * self.channel2.dac_output()
-                    + ((self.nr51 & 0b1) as f32) * self.channel1.dac_output())
-                    / 4.0;
-                self.samples[self.sample_idx * 2] =
-                    (((self.nr50 >> 4) & 0x7) as f32 / 7.0) * left_sample;
-                self.samples[self.sample_idx * 2 + 1] =
-                    ((self.nr50 & 0x7) as f32 / 7.0) * right_sample;
+            let outputs = [
+                self.channel1.dac_output(),
+                self.channel2.dac_output(),
+                self.channel3.dac_output(),
+                self.channel4.dac_output(),
+            ];
+            // the NR50 VIN bits are kept but mix nothing, none of the supported MBCs drive the pin
+            let left = self.mix(self.nr51 >> 4, &outputs) * ((self.nr50 >> 4) & 0x7) as f32 / 7.0;
+            let right = self.mix(self.nr51 & 0xF, &outputs) * (self.nr50 & 0x7) as f32 / 7.0;
+            // position of this cycle between the previous and the next host sample
+            let phase = (self.sample_phase as u64 * PHASES as u64 / CPU_CLOCK_HZ as u64) as usize;
+            self.blips[0].set_level(left, phase);
+            self.blips[1].set_level(right, phase);
+            if self.stems.is_some() {
+                for (blip, &output) in self.stem_blips.iter_mut().zip(&outputs) {
+                    blip.set_level(output, phase);
+                }
+            }
+
+            // exactly SAMPLE_RATE samples per second of emulated time
+            self.sample_phase += SAMPLE_RATE;
+            if self.sample_phase >= CPU_CLOCK_HZ {
+                self.sample_phase -= CPU_CLOCK_HZ;
+                let i = self.sample_idx * 2;
+                let (charge_factor, dacs_enabled) = (self.charge_factor, self.dacs_enabled());
+                for (terminal, sample) in self.samples[i..i + 2].iter_mut().enumerate() {
+                    let input = self.blips[terminal].read();
+                    let capacitor = &mut self.capacitors[terminal];
+                    *sample = high_pass(capacitor, charge_factor, input, dacs_enabled);
+                }
+                if let Some(recording) = &mut self.recording {
+                    recording.extend_from_slice(&self.samples[i..i + 2]);
+                }
+                if let Some(stems) = &mut self.stems {
+                    let capacitors = self.stem_capacitors.iter_mut();
+                    for (blip, capacitor) in self.stem_blips.iter_mut().zip(capacitors) {
+                        stems.push(high_pass(
+                            capacitor,
+                            charge_factor,
+                            blip.read(),
+                            dacs_enabled,
+                        ));
+                    }
+                }
                 self.sample_idx += 1;
             }
 
             if self.sample_idx >= SAMPLES {
-                self.audio.queue(self.samples.as_ref());
+                if let Some(audio) = &mut self.audio {
+                    audio.queue(self.samples.as_ref());
+                }
                 self.sample_idx = 0;
             }
         }
     }
 
+    // enables: NR51 bits of the terminal, channel 1 in bit 0
+    fn mix(&self, enables: u8, outputs: &[f32; 4]) -> f32 {
+        let mut sum = 0.0;
+        for (i, &output) in outputs.iter().enumerate() {
+            if (enables >> i) & 1 != 0 && self.is_audible(i + 1) {
+                sum += output;
+            }
+        }
+        sum / 4.0
+    }
+
+    fn dacs_enabled(&self) -> bool {
+        self.channel1.dac_enabled
+            || self.channel2.dac_enabled
+            || self.channel3.dac_enabled
+            || self.channel4.dac_enabled
+    }
+
+    // channel: 1-4
+    pub fn set_muted(&mut self, channel: usize, muted: bool) {
+        Self::set_channel_bit(&mut self.muted, channel, muted);
+    }
+
+    pub fn is_muted(&self, channel: usize) -> bool {
+        self.muted & (1 << (channel - 1)) != 0
+    }
+
+    // while any channel is soloed, only soloed channels are heard
+    pub fn set_solo(&mut self, channel: usize, solo: bool) {
+        Self::set_channel_bit(&mut self.solo, channel, solo);
+    }
+
+    pub fn is_solo(&self, channel: usize) -> bool {
+        self.solo & (1 << (channel - 1)) != 0
+    }
+
+    fn set_channel_bit(bits: &mut u8, channel: usize, val: bool) {
+        assert!((1..=4).contains(&channel), "invalid channel: {}", channel);
+        if val {
+            *bits |= 1 << (channel - 1);
+        } else {
+            *bits &= !(1 << (channel - 1));
+        }
+    }
+
+    fn is_audible(&self, channel: usize) -> bool {
+        if self.solo != 0 {
+            self.is_solo(channel)
+        } else {
+            !self.is_muted(channel)
+        }
+    }
+
+    // samples not taken yet are kept while enabled
+    pub fn set_recording(&mut self, mix: bool, stems: bool) {
+        if !mix {
+            self.recording = None;
+        } else if self.recording.is_none() {
+            self.recording = Some(vec![]);
+        }
+        if !stems {
+            self.stems = None;
+        } else if self.stems.is_none() {
+            self.stems = Some(vec![]);
+            self.stem_blips = Default::default();
+            self.stem_capacitors = [0.0; 4];
+        }
+    }
+
+    // output since the last call while recording
+    pub fn take_recorded(&mut self) -> Vec<f32> {
+        self.recording
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    // channel outputs since the last call while recording stems
+    pub fn take_stems(&mut self) -> Vec<f32> {
+        self.stems.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
             0xFF10..=0xFF14 => self.channel1.read_nrxx(addr - 0xFF10),
@@ -107,7 +258,7 @@ impl Apu {
             0xFF25 => self.nr51,
             0xFF26 => {
                 let mut ret = 0;
-                ret |= (self.channel1.enabled as u8) << 0;
+                ret |= self.channel1.enabled as u8;
                 ret |= (self.channel2.enabled as u8) << 1;
                 ret |= (self.channel3.enabled as u8) << 2;
                 ret |= (self.channel4.enabled as u8) << 3;
@@ -159,3 +310,144 @@ impl Apu {
         }
     }
 }
+
+// removes the DC offset of the DACs, so that the output decays to 0 instead of
+// staying at the level of a silent channel. while all DACs are off the output is 0,
+// and the capacitor neither charges nor discharges until a DAC is enabled again
+fn high_pass(capacitor: &mut f32, charge_factor: f32, input: f32, dacs_enabled: bool) -> f32 {
+    if !dacs_enabled {
+        return 0.0;
+    }
+    let output = input - *capacitor;
+    *capacitor = input - output * charge_factor;
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_sequencer_steps_every_8192_cycles() {
+        let mut apu = Apu::new(None, false);
+        apu.write(0xFF26, 0x80);
+        // channel 2: length 2, DAC on, triggered with the length enabled
+        apu.write(0xFF16, 0x3E);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0xC0);
+        // step 0 clocks the length on the first cycle, the next length step (2) 16384 cycles later
+        apu.emulate_cycle(1);
+        assert_eq!(apu.read(0xFF26) & 0x02, 0x02);
+        for _ in 0..16383 {
+            apu.emulate_cycle(1);
+        }
+        assert_eq!(apu.read(0xFF26) & 0x02, 0x02);
+        apu.emulate_cycle(1);
+        assert_eq!(apu.read(0xFF26) & 0x02, 0);
+    }
+
+    #[test]
+    fn high_pass_decay() {
+        let factor = Apu::new(None, false).charge_factor;
+        let mut capacitor = 0.0;
+        let mut prev = high_pass(&mut capacitor, factor, 0.5, true);
+        assert_eq!(prev, 0.5);
+        // a constant input decays towards 0
+        for _ in 0..SAMPLE_RATE {
+            let output = high_pass(&mut capacitor, factor, 0.5, true);
+            assert!((0.0..=prev).contains(&output));
+            prev = output;
+        }
+        assert!(prev < 0.01, "{}", prev);
+
+        // silent while all DACs are off, the charge is kept
+        let mut capacitor = 0.25;
+        assert_eq!(high_pass(&mut capacitor, factor, 0.5, false), 0.0);
+        assert_eq!(capacitor, 0.25);
+        assert_eq!(high_pass(&mut capacitor, factor, 0.5, true), 0.25);
+    }
+
+    #[test]
+    fn stems_are_filtered() {
+        let mut apu = Apu::new(None, false);
+        apu.write(0xFF26, 0x80);
+        // channel 2 DAC on but the channel not triggered: constant 1.0
+        apu.write(0xFF17, 0xF0);
+        apu.set_recording(false, true);
+        for _ in 0..CPU_CLOCK_HZ / 4 {
+            apu.emulate_cycle(4);
+        }
+        let stems = apu.take_stems();
+        // once the band-limited step has settled
+        let first = &stems[16 * 4..17 * 4];
+        assert!(first[1] > 0.9, "{:?}", first);
+        let last = &stems[stems.len() - 4..];
+        assert!(last[1].abs() < 0.01, "{:?}", last);
+        assert_eq!(last[0], 0.0);
+    }
+
+    #[test]
+    fn mute_and_solo() {
+        let mut apu = Apu::new(None, false);
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF25, 0xFF);
+        // channels 1 and 2 playing
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x80);
+        apu.write(0xFF17, 0xF0);
+        apu.write(0xFF19, 0x80);
+        let nr51 = apu.read(0xFF25);
+        let nr52 = apu.read(0xFF26);
+        assert_eq!(nr52 & 0x0F, 0x03);
+
+        // only the mix is affected, the game sees the registers as written
+        apu.set_muted(1, true);
+        assert!(!apu.is_audible(1));
+        assert!(apu.is_audible(2));
+        assert_eq!((apu.read(0xFF25), apu.read(0xFF26)), (nr51, nr52));
+
+        // solo overrides mute
+        apu.set_solo(1, true);
+        assert!(apu.is_audible(1));
+        assert!(!apu.is_audible(2));
+        assert!(!apu.is_audible(3));
+        assert_eq!((apu.read(0xFF25), apu.read(0xFF26)), (nr51, nr52));
+
+        apu.set_solo(1, false);
+        apu.set_muted(1, false);
+        assert!((1..=4).all(|channel| apu.is_audible(channel)));
+        assert_eq!((apu.read(0xFF25), apu.read(0xFF26)), (nr51, nr52));
+    }
+
+    #[test]
+    fn sample_rate() {
+        let mut apu = Apu::new(None, false);
+        apu.set_recording(true, false);
+        // one emulated second
+        for _ in 0..CPU_CLOCK_HZ / 4 {
+            apu.emulate_cycle(4);
+        }
+        assert_eq!(apu.take_recorded().len() as u128, SAMPLE_RATE * 2);
+    }
+
+    #[test]
+    fn dac_output() {
+        let mut apu = Apu::new(None, false);
+        apu.write(0xFF26, 0x80);
+        // channel 3 DAC on, wave RAM all 15 at full volume
+        for addr in 0xFF30..=0xFF3F {
+            apu.write(addr, 0xFF);
+        }
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1C, 0x20);
+        assert_eq!(apu.channel3.dac_output(), 1.0);
+        apu.write(0xFF1E, 0x80);
+        for _ in 0..16 {
+            apu.emulate_cycle(4);
+        }
+        assert_eq!(apu.channel3.dac_output(), -1.0);
+        // DAC off
+        apu.write(0xFF1A, 0x00);
+        assert_eq!(apu.channel3.dac_output(), 0.0);
+    }
+}
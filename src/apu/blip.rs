@@ -0,0 +1,117 @@
+use std::{f64::consts::PI, sync::OnceLock};
+
+// band-limited step synthesis: each amplitude change is added to the output as a
+// windowed-sinc step at its exact position between two host samples, so square waves
+// and noise do not alias like point sampling
+// http://www.slack.net/~ant/bl-synth/
+
+// kernel taps, the output is delayed by half of it
+const WIDTH: usize = 16;
+// resolution of a step position between two output samples
+pub const PHASES: usize = 64;
+// passband, relative to the Nyquist frequency of the output
+const CUTOFF: f64 = 0.9;
+// each kernel row sums to KERNEL_UNIT, so the integrated steps do not drift
+const KERNEL_UNIT: i64 = 1 << 15;
+const LEVEL_UNIT: f32 = (1 << 15) as f32; // amplitude 1.0
+
+fn kernel() -> &'static [[i32; WIDTH]; PHASES] {
+    static KERNEL: OnceLock<[[i32; WIDTH]; PHASES]> = OnceLock::new();
+    KERNEL.get_or_init(|| {
+        let half = (WIDTH / 2) as f64;
+        let mut kernel = [[0; WIDTH]; PHASES];
+        for (phase, row) in kernel.iter_mut().enumerate() {
+            // impulse at tap WIDTH / 2 - 1 + phase / PHASES
+            let taps: Vec<f64> = (0..WIDTH)
+                .map(|k| {
+                    let x = k as f64 - (half - 1.0) - phase as f64 / PHASES as f64;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        (PI * CUTOFF * x).sin() / (PI * CUTOFF * x)
+                    };
+                    // blackman window over [-half, half]
+                    let u = (x + half) / (2.0 * half);
+                    let window = 0.42 - 0.5 * (2.0 * PI * u).cos() + 0.08 * (4.0 * PI * u).cos();
+                    sinc * window
+                })
+                .collect();
+            let total: f64 = taps.iter().sum();
+            for (tap, t) in row.iter_mut().zip(&taps) {
+                *tap = (t / total * KERNEL_UNIT as f64).round() as i32;
+            }
+            // rounding error to the center tap
+            row[WIDTH / 2 - 1] += KERNEL_UNIT as i32 - row.iter().sum::<i32>();
+        }
+        kernel
+    })
+}
+
+// one output signal
+#[derive(Default, Clone, Copy)]
+pub struct Blip {
+    deltas: [i64; WIDTH], // ring buffer of the derivative, from the next output sample
+    head: usize,
+    level: i32,
+    sum: i64, // integrated deltas of the output samples read
+}
+
+impl Blip {
+    // amplitude from now on, phase: position of the current clock between the previous
+    // and the next output sample, 0..PHASES
+    pub fn set_level(&mut self, level: f32, phase: usize) {
+        let level = (level * LEVEL_UNIT).round() as i32;
+        let delta = (level - self.level) as i64;
+        if delta == 0 {
+            return;
+        }
+        self.level = level;
+        for (k, &tap) in kernel()[phase].iter().enumerate() {
+            self.deltas[(self.head + k) % WIDTH] += delta * tap as i64;
+        }
+    }
+
+    // the next output sample
+    pub fn read(&mut self) -> f32 {
+        self.sum += self.deltas[self.head];
+        self.deltas[self.head] = 0;
+        self.head = (self.head + 1) % WIDTH;
+        self.sum as f32 / (KERNEL_UNIT as f32 * LEVEL_UNIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernel_rows_sum_to_unit() {
+        for row in kernel() {
+            assert_eq!(row.iter().map(|&tap| tap as i64).sum::<i64>(), KERNEL_UNIT);
+        }
+    }
+
+    #[test]
+    fn steps_settle_without_drift() {
+        let mut blip = Blip::default();
+        for i in 0..10000 {
+            // levels exact in LEVEL_UNIT, steps at every phase
+            let level = ((i * 7) % 17) as f32 / 8.0 - 1.0;
+            blip.set_level(level, i % PHASES);
+            for _ in 0..WIDTH {
+                blip.read();
+            }
+            assert_eq!(blip.read(), level);
+        }
+        // several steps between two output samples
+        for phase in 0..PHASES {
+            blip.set_level(phase as f32 / PHASES as f32, phase);
+        }
+        let level = (PHASES - 1) as f32 / PHASES as f32;
+        for _ in 0..WIDTH {
+            blip.read();
+        }
+        assert_eq!(blip.read(), level);
+        assert_eq!(blip.sum, blip.level as i64 * KERNEL_UNIT);
+    }
+}
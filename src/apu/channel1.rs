@@ -3,7 +3,7 @@ use std::cmp::min;
 
 #[derive(Default)]
 pub struct Channel1 {
-    dac_enabled: bool,
+    pub dac_enabled: bool,
     frequency: u16,
     frequency_timer: u16,
     pub wave_duty_position: usize,
@@ -32,7 +32,7 @@ impl Channel1 {
         if fs == 7 {
             self.envelope();
         }
-        if fs == 6 || fs == 6 {
+        if fs == 2 || fs == 6 {
             self.sweep();
         }
     }
@@ -80,11 +80,8 @@ impl Channel1 {
 
     fn calculate_frequency(&mut self) -> u16 {
         if self.is_decrementing {
-            if self.shadow_frequency >= (self.shadow_frequency >> self.sweep_shift) {
-                self.shadow_frequency - (self.shadow_frequency >> self.sweep_shift)
-            } else {
-                0
-            }
+            self.shadow_frequency
+                .saturating_sub(self.shadow_frequency >> self.sweep_shift)
         } else {
             min(
                 0x3FF,
@@ -103,13 +100,16 @@ impl Channel for Channel1 {
     }
 
     fn dac_output(&self) -> f32 {
-        if self.dac_enabled && self.enabled {
-            let ret = WAVE_DUTY[self.wave_duty_pattern as usize][self.wave_duty_position as usize]
-                * self.current_volume as f32;
-            (ret / 7.5) - 1.0
+        if !self.dac_enabled {
+            return 0.0;
+        }
+        let ret = if self.enabled {
+            WAVE_DUTY[self.wave_duty_pattern as usize][self.wave_duty_position]
+                * self.current_volume as f32
         } else {
             0.0
-        }
+        };
+        1.0 - ret / 7.5
     }
 
     fn read_nrxx(&self, x: u16) -> u8 {
@@ -168,3 +168,29 @@ impl Channel for Channel1 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_steps() {
+        let mut channel = Channel1::default();
+        // sweep period 1, shift 1, upwards
+        channel.write_nrxx(0, 0x11);
+        channel.write_nrxx(2, 0xF0);
+        channel.write_nrxx(3, 0x00);
+        channel.write_nrxx(4, 0x81);
+
+        let mut steps = vec![];
+        for fs in 0..8 {
+            let frequency = channel.frequency;
+            channel.emulate_fs_cycle(fs);
+            if channel.frequency != frequency {
+                steps.push(fs);
+            }
+        }
+        assert_eq!(steps, [2, 6]);
+        assert_eq!(channel.frequency, 0x240);
+    }
+}
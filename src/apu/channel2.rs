@@ -2,7 +2,7 @@ use super::{Channel, WAVE_DUTY};
 
 #[derive(Default)]
 pub struct Channel2 {
-    dac_enabled: bool,
+    pub dac_enabled: bool,
     frequency: u16,
     frequency_timer: u16,
     pub wave_duty_position: usize,
@@ -64,13 +64,16 @@ impl Channel for Channel2 {
     }
 
     fn dac_output(&self) -> f32 {
-        if self.dac_enabled && self.enabled {
-            let ret = WAVE_DUTY[self.wave_duty_pattern as usize][self.wave_duty_position as usize]
-                * self.current_volume as f32;
-            (ret / 7.5) - 1.0
+        if !self.dac_enabled {
+            return 0.0;
+        }
+        let ret = if self.enabled {
+            WAVE_DUTY[self.wave_duty_pattern as usize][self.wave_duty_position]
+                * self.current_volume as f32
         } else {
             0.0
-        }
+        };
+        1.0 - ret / 7.5
     }
 
     fn read_nrxx(&self, x: u16) -> u8 {
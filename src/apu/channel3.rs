@@ -4,7 +4,7 @@ use super::Channel;
 
 #[derive(Default)]
 pub struct Channel3 {
-    dac_enabled: bool,
+    pub dac_enabled: bool,
     frequency: u16,
     frequency_timer: u16,
     pub wave_duty_position: usize,
@@ -40,15 +40,17 @@ impl Channel for Channel3 {
     }
 
     fn dac_output(&self) -> f32 {
-        if self.dac_enabled && self.enabled {
-            let ret = (0xF
-                & (self.wave_ram[self.wave_duty_position >> 1]
-                    >> ((self.wave_duty_position & 1) << 2))
-                    >> self.volume_shift) as f32;
-            (ret / 7.5) - 1.0
+        if !self.dac_enabled {
+            return 0.0;
+        }
+        let ret = if self.enabled {
+            (0xF & (self.wave_ram[self.wave_duty_position >> 1]
+                >> ((self.wave_duty_position & 1) << 2))
+                >> self.volume_shift) as f32
         } else {
             0.0
-        }
+        };
+        1.0 - ret / 7.5
     }
 
     fn read_nrxx(&self, x: u16) -> u8 {
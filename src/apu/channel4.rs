@@ -4,7 +4,7 @@ use super::Channel;
 
 #[derive(Default)]
 pub struct Channel4 {
-    dac_enabled: bool,
+    pub dac_enabled: bool,
     frequency_timer: u16,
     length_timer: u8,
     length_enabled: bool,
@@ -71,12 +71,15 @@ impl Channel for Channel4 {
     }
 
     fn dac_output(&self) -> f32 {
-        if self.dac_enabled && self.enabled {
-            let ret = (self.lfsr & 1) as f32 * self.current_volume as f32;
-            (ret / 7.5) - 1.0
+        if !self.dac_enabled {
+            return 0.0;
+        }
+        let ret = if self.enabled {
+            (self.lfsr & 1) as f32 * self.current_volume as f32
         } else {
             0.0
-        }
+        };
+        1.0 - ret / 7.5
     }
 
     fn read_nrxx(&self, x: u16) -> u8 {
@@ -0,0 +1,330 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+// minimal AVI 1.0 writer: uncompressed 24-bit RGB video and 16-bit stereo PCM audio,
+// one video chunk and the audio of that frame interleaved per frame
+// https://learn.microsoft.com/en-us/windows/win32/directshow/avi-riff-file-reference
+
+// AVI 1.0 offsets are 32 bits and many players stop at 1 GiB, the caller starts a new file
+pub const MAX_SIZE: u64 = 1 << 30;
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIF_ISINTERLEAVED: u32 = 0x100;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+pub struct AviWriter {
+    file: BufWriter<File>,
+    width: usize,
+    height: usize,
+    frames: u32,
+    samples: u32,                    // stereo sample frames
+    index: Vec<([u8; 4], u32, u32)>, // chunk id, offset from "movi", size
+    movi: u64,                       // position of the "movi" list type
+    size: u64,
+    // header fields patched when finished
+    total_frames_pos: u64,
+    video_length_pos: u64,
+    audio_length_pos: u64,
+}
+
+impl AviWriter {
+    // frame rate: rate / scale frames per second
+    pub fn create(
+        path: &Path,
+        width: usize,
+        height: usize,
+        rate: u32,
+        scale: u32,
+        sample_rate: u32,
+    ) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let frame_size = (width * height * 3) as u32;
+        let micros_per_frame = (1_000_000 * scale as u64 / rate as u64) as u32;
+        let bytes_per_sec =
+            (frame_size as u64 * rate as u64 / scale as u64) as u32 + sample_rate * 4;
+
+        let mut header = vec![];
+        header.extend_from_slice(b"RIFF\0\0\0\0AVI LIST\0\0\0\0hdrl");
+        // main header
+        let total_frames_pos = header.len() as u64 + 24;
+        chunk(
+            &mut header,
+            b"avih",
+            &[
+                micros_per_frame,
+                bytes_per_sec,
+                0,
+                AVIF_HASINDEX | AVIF_ISINTERLEAVED,
+                0, // total frames
+                0,
+                2, // streams
+                frame_size,
+                width as u32,
+                height as u32,
+                0,
+                0,
+                0,
+                0,
+            ],
+        );
+        // video stream
+        let video = header.len();
+        header.extend_from_slice(b"LIST\0\0\0\0strl");
+        let frame = [0, 0, width as u16, height as u16];
+        let strh = stream_header(b"vids", b"DIB ", scale, rate, frame_size, 0, frame);
+        raw_chunk(&mut header, b"strh", &strh);
+        let mut bitmap = vec![];
+        for val in [40, width as u32, height as u32] {
+            bitmap.extend_from_slice(&val.to_le_bytes());
+        }
+        bitmap.extend_from_slice(&1u16.to_le_bytes()); // planes
+        bitmap.extend_from_slice(&24u16.to_le_bytes()); // bits per pixel
+        for val in [0, frame_size, 0, 0, 0, 0] {
+            bitmap.extend_from_slice(&val.to_le_bytes());
+        }
+        raw_chunk(&mut header, b"strf", &bitmap);
+        patch_list_size(&mut header, video);
+        // audio stream
+        let audio = header.len();
+        header.extend_from_slice(b"LIST\0\0\0\0strl");
+        let strh = stream_header(b"auds", &[0; 4], 1, sample_rate, sample_rate * 4, 4, [0; 4]);
+        raw_chunk(&mut header, b"strh", &strh);
+        let mut format = vec![];
+        format.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        format.extend_from_slice(&2u16.to_le_bytes()); // channels
+        format.extend_from_slice(&sample_rate.to_le_bytes());
+        format.extend_from_slice(&(sample_rate * 4).to_le_bytes());
+        format.extend_from_slice(&4u16.to_le_bytes()); // block align
+        format.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+        format.extend_from_slice(&0u16.to_le_bytes());
+        raw_chunk(&mut header, b"strf", &format);
+        patch_list_size(&mut header, audio);
+        let hdrl_size = (header.len() - 20) as u32;
+        header[16..20].copy_from_slice(&hdrl_size.to_le_bytes());
+        header.extend_from_slice(b"LIST\0\0\0\0movi");
+        file.write_all(&header)?;
+
+        Ok(Self {
+            file,
+            width,
+            height,
+            frames: 0,
+            samples: 0,
+            index: vec![],
+            movi: header.len() as u64 - 4,
+            size: header.len() as u64,
+            total_frames_pos,
+            // dwLength of the stream headers
+            video_length_pos: video as u64 + 52,
+            audio_length_pos: audio as u64 + 52,
+        })
+    }
+
+    // size of the file if finished now, the index adds 16 bytes per chunk
+    pub fn size(&self) -> u64 {
+        self.size + 8 + self.index.len() as u64 * 16
+    }
+
+    // pixels: RGB24, top to bottom
+    pub fn write_frame(&mut self, pixels: &[u8]) -> io::Result<()> {
+        // bottom-up BGR
+        let mut data = Vec::with_capacity(pixels.len());
+        for row in pixels.chunks(self.width * 3).rev() {
+            for rgb in row.chunks(3) {
+                data.extend_from_slice(&[rgb[2], rgb[1], rgb[0]]);
+            }
+        }
+        debug_assert_eq!(data.len(), self.width * self.height * 3);
+        self.frames += 1;
+        self.write_chunk(*b"00dc", &data)
+    }
+
+    // samples: interleaved stereo
+    pub fn write_audio(&mut self, samples: &[i16]) -> io::Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.samples += samples.len() as u32 / 2;
+        self.write_chunk(*b"01wb", &data)
+    }
+
+    fn write_chunk(&mut self, id: [u8; 4], data: &[u8]) -> io::Result<()> {
+        self.index
+            .push((id, (self.size - self.movi) as u32, data.len() as u32));
+        self.file.write_all(&id)?;
+        self.file.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.size += 8 + data.len() as u64;
+        if data.len() & 1 != 0 {
+            self.file.write_all(&[0])?;
+            self.size += 1;
+        }
+        Ok(())
+    }
+
+    // write the index and the sizes in the headers
+    pub fn finish(mut self) -> io::Result<()> {
+        let movi_size = (self.size - self.movi) as u32;
+        let mut idx1 = vec![];
+        for (id, offset, size) in &self.index {
+            idx1.extend_from_slice(id);
+            for val in [AVIIF_KEYFRAME, *offset, *size] {
+                idx1.extend_from_slice(&val.to_le_bytes());
+            }
+        }
+        let mut chunk = vec![];
+        raw_chunk(&mut chunk, b"idx1", &idx1);
+        self.file.write_all(&chunk)?;
+        let riff_size = (self.size + chunk.len() as u64 - 8) as u32;
+
+        for (pos, val) in [
+            (4, riff_size),
+            (self.movi - 4, movi_size),
+            (self.total_frames_pos, self.frames),
+            (self.video_length_pos, self.frames),
+            (self.audio_length_pos, self.samples),
+        ] {
+            self.file.seek(SeekFrom::Start(pos))?;
+            self.file.write_all(&val.to_le_bytes())?;
+        }
+        self.file.flush()
+    }
+}
+
+fn raw_chunk(buf: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    buf.extend_from_slice(id);
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn chunk(buf: &mut Vec<u8>, id: &[u8; 4], fields: &[u32]) {
+    let data: Vec<u8> = fields.iter().flat_map(|f| f.to_le_bytes()).collect();
+    raw_chunk(buf, id, &data);
+}
+
+// AVISTREAMHEADER, the length is patched when finished
+fn stream_header(
+    kind: &[u8; 4],
+    handler: &[u8; 4],
+    scale: u32,
+    rate: u32,
+    buffer_size: u32,
+    sample_size: u32,
+    frame: [u16; 4],
+) -> Vec<u8> {
+    let mut header = vec![];
+    header.extend_from_slice(kind);
+    header.extend_from_slice(handler);
+    // flags, priority / language, initial frames, scale, rate, start, length,
+    // buffer size, quality (default), sample size
+    for val in [
+        0,
+        0,
+        0,
+        scale,
+        rate,
+        0,
+        0,
+        buffer_size,
+        u32::MAX,
+        sample_size,
+    ] {
+        header.extend_from_slice(&val.to_le_bytes());
+    }
+    for val in frame {
+        header.extend_from_slice(&val.to_le_bytes());
+    }
+    header
+}
+
+fn patch_list_size(buf: &mut [u8], pos: usize) {
+    let size = (buf.len() - pos - 8) as u32;
+    buf[pos + 4..pos + 8].copy_from_slice(&size.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn two_frames() {
+        let path = temp_path("two-frames.avi");
+        // 1x1 frames, the 3-byte video chunks are padded
+        let mut avi = AviWriter::create(&path, 1, 1, 60, 1, 48000).unwrap();
+        avi.write_frame(&[0x11, 0x22, 0x33]).unwrap();
+        avi.write_audio(&[1, 2, 3, 4]).unwrap();
+        avi.write_frame(&[0x44, 0x55, 0x66]).unwrap();
+        avi.write_audio(&[5, 6, 7, 8]).unwrap();
+        let size = avi.size();
+        avi.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(size, data.len() as u64);
+
+        assert_eq!(data[..4], *b"RIFF");
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(data[8..12], *b"AVI ");
+
+        assert_eq!(data[12..16], *b"LIST");
+        assert_eq!(data[20..24], *b"hdrl");
+        let hdrl_size = u32_at(&data, 16) as usize;
+        assert_eq!(data[24..28], *b"avih");
+        assert_eq!(u32_at(&data, 28), 56);
+        assert_eq!(u32_at(&data, 32), 16666); // microseconds per frame
+        assert_eq!(u32_at(&data, 48), 2); // total frames
+
+        // stream lengths: frames and audio samples
+        let strh = |n: usize| {
+            let pos = (24..hdrl_size + 20)
+                .filter(|&i| data[i..i + 4] == *b"strh")
+                .nth(n)
+                .unwrap();
+            (
+                data[pos + 8..pos + 12].to_vec(),
+                u32_at(&data, pos + 8 + 32),
+            )
+        };
+        assert_eq!(strh(0), (b"vids".to_vec(), 2));
+        assert_eq!(strh(1), (b"auds".to_vec(), 4));
+
+        // movi right after hdrl
+        let movi = 20 + hdrl_size;
+        assert_eq!(data[movi..movi + 4], *b"LIST");
+        assert_eq!(data[movi + 8..movi + 12], *b"movi");
+        let movi_size = u32_at(&data, movi + 4) as usize;
+        assert_eq!(movi_size, 4 + (8 + 4) + (8 + 8) + (8 + 4) + (8 + 8));
+        // bottom-up BGR and a pad byte
+        assert_eq!(data[movi + 12..movi + 24], *b"00dc\x03\0\0\0\x33\x22\x11\0");
+
+        let idx1 = movi + 8 + movi_size;
+        assert_eq!(data[idx1..idx1 + 4], *b"idx1");
+        assert_eq!(u32_at(&data, idx1 + 4), 4 * 16);
+        assert_eq!(idx1 + 8 + 4 * 16, data.len());
+        let entries = [
+            (b"00dc", 4, 3),
+            (b"01wb", 16, 8),
+            (b"00dc", 32, 3),
+            (b"01wb", 44, 8),
+        ];
+        for (i, (id, offset, size)) in entries.into_iter().enumerate() {
+            let entry = idx1 + 8 + i * 16;
+            assert_eq!(data[entry..entry + 4], *id);
+            assert_eq!(u32_at(&data, entry + 4), AVIIF_KEYFRAME);
+            assert_eq!(u32_at(&data, entry + 8), offset);
+            assert_eq!(u32_at(&data, entry + 12), size);
+            // offsets from the "movi" list type
+            let chunk = movi + 8 + offset as usize;
+            assert_eq!(data[chunk..chunk + 4], *id);
+            assert_eq!(u32_at(&data, chunk + 4), size);
+        }
+    }
+}
@@ -8,6 +8,14 @@ impl BootRom {
         Self { rom, active: true }
     }
 
+    // no boot ROM, the cartridge starts with the post-boot register state
+    pub fn none() -> Self {
+        Self {
+            rom: Box::new([]),
+            active: false,
+        }
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         self.rom[addr as usize]
     }
@@ -16,6 +24,17 @@ impl BootRom {
         self.active
     }
 
+    // DMG boot ROM: 0x0000-0x00FF
+    // CGB boot ROM: 0x0000-0x00FF and 0x0200-0x08FF, the cartridge header is visible in between
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        self.active
+            && match addr {
+                0x0000..=0x00FF => true,
+                0x0200..=0x08FF => self.rom.len() > addr as usize,
+                _ => false,
+            }
+    }
+
     // Boot ROM に対する書き込みができるわけではなさそうなので、addr, val は Pheripherals に移動させた方が良いかも？
     pub fn write(&mut self, _: u16, val: u8) {
         self.active &= val == 0;
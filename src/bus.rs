@@ -2,11 +2,15 @@ use crate::apu::Apu;
 use crate::audio::Audio;
 use crate::bootrom::BootRom;
 use crate::cartridge::Cartridge;
+use crate::cdl::{self, Cdl};
 use crate::cpu::interrupt::Interrupts;
+use crate::gameboy::M_CYCLE_CLOCK;
+use crate::hdma::Hdma;
 use crate::hram::HRam;
 use crate::joypad::Joypad;
-use crate::lcd::LCD;
-use crate::ppu::Ppu;
+use crate::lcd::Lcd;
+use crate::ppu::{compat_palette, Ppu};
+use crate::sgb::Sgb;
 use crate::timer::Timer;
 use crate::wram::WRam;
 
@@ -17,6 +21,11 @@ const HRAM_ADDR_END: u16 = 0xFFFE;
 const BOOTROM_ADDR_START: u16 = 0x0000;
 const BOOTROM_ADDR_END: u16 = 0x00FF;
 const BOOTROM_DEACTIVE_ADDR: u16 = 0xFF50;
+const SVBK_ADDR: u16 = 0xFF70;
+const KEY0_ADDR: u16 = 0xFF4C;
+const KEY1_ADDR: u16 = 0xFF4D;
+const HDMA_ADDR_START: u16 = 0xFF51;
+const HDMA_ADDR_END: u16 = 0xFF55;
 const CARTRIDGE_ADDR1_START: u16 = 0x0100;
 const CARTRIDGE_ADDR1_END: u16 = 0x7FFF;
 const CARTRIDGE_ADDR2_START: u16 = 0xA000;
@@ -28,10 +37,15 @@ const JOYPAD_ADDR: u16 = 0xFF00;
 // ppu
 const PPU_REGISTER_START: u16 = 0xFF40;
 const PPU_REGISTER_END: u16 = 0xFF4B;
+const VBK_ADDR: u16 = 0xFF4F;
+const PALETTE_REGISTER_START: u16 = 0xFF68; // BCPS, BCPD, OCPS, OCPD
+const PALETTE_REGISTER_END: u16 = 0xFF6B;
 const VRAM_ADDR_START: u16 = 0x8000;
 const VRAM_ADDR_END: u16 = 0x9FFF;
+const VRAM_TILE_DATA_END: u16 = 0x9800;
 const OAM_ADDR_START: u16 = 0xFE00;
 const OAM_ADDR_END: u16 = 0xFE9F;
+const IO_ADDR_START: u16 = 0xFF00;
 
 pub struct Bus {
     pub bootrom: BootRom,
@@ -41,38 +55,109 @@ pub struct Bus {
     pub apu: Apu,
     pub timer: Timer,
     pub joypad: Joypad,
+    pub hdma: Hdma,
+    pub cdl: Option<Cdl>,
     cartridge: Cartridge,
+    cgb: bool,          // CGB mode, cleared in DMG compatibility mode
+    double_speed: bool, // CGB
+    speed_switch: bool, // KEY1 bit 0, switch speed on the next STOP
+    pub clocks: u128,   // elapsed clocks at normal speed, for pacing
 }
 
 impl Bus {
-    pub fn new(bootrom: BootRom, cartridge: Cartridge, lcd: LCD, audio: Audio) -> Self {
+    // cgb: CGB hardware, DMG games run in DMG compatibility mode after boot
+    // lcd, audio: None to run without output
+    pub fn new(
+        bootrom: BootRom,
+        cartridge: Cartridge,
+        lcd: Option<Lcd>,
+        audio: Option<Audio>,
+        cgb: bool,
+    ) -> Self {
         Self {
             bootrom,
             wram: WRam::new(),
             hram: HRam::new(),
-            ppu: Ppu::new(lcd),
-            apu: Apu::new(audio),
+            ppu: Ppu::new(lcd, cgb),
+            apu: Apu::new(audio, cgb),
             timer: Timer::default(),
             joypad: Joypad::new(),
+            hdma: Hdma::default(),
+            cdl: None,
             cartridge,
+            cgb,
+            double_speed: false,
+            speed_switch: false,
+            clocks: 0,
+        }
+    }
+
+    fn enter_compat_mode(&mut self) {
+        self.cgb = false;
+        self.ppu.enter_compat_mode();
+    }
+
+    // border and palettes of the SGB, commands are received only from SGB enhanced games
+    pub fn enable_sgb(&mut self) {
+        self.ppu.sgb = Some(Sgb::new());
+        if self.cartridge.supports_sgb() {
+            self.joypad.enable_sgb();
+        }
+    }
+
+    // I/O registers as left by the boot ROM
+    pub fn skip_bootrom(&mut self) {
+        if self.cgb && !self.cartridge.is_cgb() {
+            let palette = compat_palette::lookup(self.cartridge.compat_palette_key());
+            self.ppu.load_compat_palette(palette);
+            self.enter_compat_mode();
+        }
+        self.apu.write(0xFF26, 0x80); // NR52
+        self.apu.write(0xFF24, 0x77); // NR50
+        self.apu.write(0xFF25, 0xF3); // NR51
+        self.ppu.write(0xFF47, 0xFC); // BGP
+        self.ppu.write(0xFF40, 0x91); // LCDC
+    }
+
+    // during OAM DMA the CPU can not access OAM, nor the bus the transfer reads from:
+    // the VRAM bus or the external bus (cartridge and WRAM). the DMG layout, also used
+    // for the CGB
+    fn oam_dma_conflict(&self, addr: u16) -> bool {
+        let vram = |addr| (VRAM_ADDR_START..=VRAM_ADDR_END).contains(&addr);
+        match self.ppu.oam_dma {
+            Some(_) if (OAM_ADDR_START..IO_ADDR_START).contains(&addr) => true,
+            Some(src) => addr < OAM_ADDR_START && vram(addr) == vram(src),
+            None => false,
         }
     }
 
     pub fn read(&self, interrupts: &Interrupts, addr: u16) -> u8 {
+        if self.oam_dma_conflict(addr) {
+            // the CPU sees the byte being transferred
+            return match addr {
+                OAM_ADDR_START..=OAM_ADDR_END => 0xFF,
+                _ => self.ppu.oam_dma_byte,
+            };
+        }
+        self.read_unrestricted(interrupts, addr)
+    }
+
+    fn read_unrestricted(&self, interrupts: &Interrupts, addr: u16) -> u8 {
         match addr {
-            BOOTROM_ADDR_START..=BOOTROM_ADDR_END => {
-                if self.bootrom.is_active() {
-                    self.bootrom.read(addr)
-                } else {
-                    self.cartridge.read(addr)
-                }
-            }
+            _ if self.bootrom.is_mapped(addr) => self.bootrom.read(addr),
+            BOOTROM_ADDR_START..=BOOTROM_ADDR_END => self.cartridge.read(addr),
             WRAM_ADDR_START..=WRAM_ADDR_END => self.wram.read(addr),
             HRAM_ADDR_START..=HRAM_ADDR_END => self.hram.read(addr),
             CARTRIDGE_ADDR1_START..=CARTRIDGE_ADDR1_END => self.cartridge.read(addr),
             CARTRIDGE_ADDR2_START..=CARTRIDGE_ADDR2_END => self.cartridge.read(addr),
             TIMER_ADDR_START..=TIMER_ADDR_END => self.timer.read(addr),
             PPU_REGISTER_START..=PPU_REGISTER_END => self.ppu.read(addr),
+            VBK_ADDR | PALETTE_REGISTER_START..=PALETTE_REGISTER_END => self.ppu.read(addr),
+            SVBK_ADDR if self.cgb => self.wram.read_svbk(),
+            HDMA_ADDR_START..=HDMA_ADDR_END if self.cgb => self.hdma.read(addr),
+            KEY1_ADDR if self.cgb => {
+                0x7E | (self.double_speed as u8) << 7 | self.speed_switch as u8
+            }
             0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.read(addr),
             VRAM_ADDR_START..=VRAM_ADDR_END => self.ppu.read(addr),
             OAM_ADDR_START..=OAM_ADDR_END => self.ppu.read(addr),
@@ -82,13 +167,57 @@ impl Bus {
         }
     }
 
+    pub fn enable_cdl(&mut self) {
+        self.cdl = Some(Cdl::new(self.cartridge.rom_size()));
+    }
+
+    // physical ROM offset of addr, None if addr is not mapped to the cartridge ROM
+    pub fn rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            _ if self.bootrom.is_mapped(addr) => None,
+            0x0000..=CARTRIDGE_ADDR1_END => Some(self.cartridge.rom_offset(addr)),
+            _ => None,
+        }
+    }
+
+    // ROM bank of addr, None if addr is not mapped to the cartridge ROM
+    pub fn rom_bank(&self, addr: u16) -> Option<usize> {
+        self.rom_offset(addr).map(|offset| offset >> 14)
+    }
+
+    // record how a byte the CPU read from addr was used in the code/data log
+    pub fn log_read(&mut self, addr: u16, flag: u8, val: u8) {
+        if self.cdl.is_none() {
+            return;
+        }
+        if let Some(offset) = self.rom_offset(addr) {
+            if let Some(cdl) = &mut self.cdl {
+                cdl.log_cpu_read(offset, flag, val);
+            }
+        }
+    }
+
+    // OAM DMA and VRAM DMA reads, kept apart from the CPU copy detection
+    fn log_dma_read(&mut self, addr: u16, flag: u8) {
+        if self.cdl.is_none() {
+            return;
+        }
+        if let Some(offset) = self.rom_offset(addr) {
+            if let Some(cdl) = &mut self.cdl {
+                cdl.log(offset, flag);
+            }
+        }
+    }
+
     pub fn write(&mut self, interrupts: &mut Interrupts, addr: u16, val: u8) {
+        if self.oam_dma_conflict(addr) {
+            return;
+        }
         match addr {
-            BOOTROM_ADDR_START..=BOOTROM_ADDR_END => {
-                if !self.bootrom.is_active() {
-                    self.cartridge.write(addr, val)
-                }
+            BOOTROM_ADDR_START..=BOOTROM_ADDR_END if !self.bootrom.is_active() => {
+                self.cartridge.write(addr, val)
             }
+            BOOTROM_ADDR_START..=BOOTROM_ADDR_END => {}
             WRAM_ADDR_START..=WRAM_ADDR_END => self.wram.write(addr, val),
             BOOTROM_DEACTIVE_ADDR => self.bootrom.write(addr, val),
             HRAM_ADDR_START..=HRAM_ADDR_END => self.hram.write(addr, val),
@@ -96,29 +225,376 @@ impl Bus {
             CARTRIDGE_ADDR2_START..=CARTRIDGE_ADDR2_END => self.cartridge.write(addr, val),
             TIMER_ADDR_START..=TIMER_ADDR_END => self.timer.write(addr, val),
             PPU_REGISTER_START..=PPU_REGISTER_END => self.ppu.write(addr, val),
+            VBK_ADDR | PALETTE_REGISTER_START..=PALETTE_REGISTER_END => self.ppu.write(addr, val),
+            SVBK_ADDR if self.cgb => self.wram.write_svbk(val),
+            KEY1_ADDR if self.cgb => self.speed_switch = val & 1 != 0,
+            // DMG mode selected by the CGB boot ROM
+            KEY0_ADDR if self.cgb && self.bootrom.is_active() && val & 0x0C == 0x04 => {
+                self.enter_compat_mode()
+            }
+            HDMA_ADDR_START..=HDMA_ADDR_END if self.cgb => self.hdma.write(addr, val),
             0xFF10..=0xFF26 | 0xFF30..=0xFF3F => self.apu.write(addr, val),
-            VRAM_ADDR_START..=VRAM_ADDR_END => self.ppu.write(addr, val),
+            VRAM_ADDR_START..=VRAM_ADDR_END => {
+                self.ppu.write(addr, val);
+                if let Some(cdl) = &mut self.cdl {
+                    if addr < VRAM_TILE_DATA_END {
+                        cdl.log_vram_write(val);
+                    }
+                }
+            }
             OAM_ADDR_START..=OAM_ADDR_END => self.ppu.write(addr, val),
-            JOYPAD_ADDR => self.joypad.write(val),
+            JOYPAD_ADDR => {
+                self.joypad.write(val);
+                if let (Some(sgb), Some(command)) =
+                    (&mut self.ppu.sgb, self.joypad.take_sgb_command())
+                {
+                    sgb.command(&command);
+                    self.joypad.set_players(sgb.players);
+                }
+            }
             0xFF0F | 0xFFFF => interrupts.write(addr, val),
             _ => (),
         }
     }
 
+    // STOP with KEY1 armed toggles the CPU speed instead of stopping the system clock
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.speed_switch {
+            return false;
+        }
+        self.speed_switch = false;
+        self.double_speed = !self.double_speed;
+        true
+    }
+
+    // one CPU M-cycle, followed by the VRAM DMA transfer the CPU is stalled for
     pub fn tick(&mut self, interrupts: &mut Interrupts) {
+        self.tick_peripherals(interrupts);
+        // 2 bytes per M-cycle, 1 byte in double speed mode (same duration at normal speed)
+        let bytes = if self.double_speed { 1 } else { 2 };
+        while self.hdma.is_transferring() {
+            for _ in 0..bytes {
+                if self.hdma.is_transferring() {
+                    let (src, dst) = self.hdma.next();
+                    let val = self.read_unrestricted(interrupts, src);
+                    let flag = if dst < VRAM_TILE_DATA_END {
+                        cdl::GRAPHICS
+                    } else {
+                        cdl::DATA
+                    };
+                    self.log_dma_read(src, flag);
+                    self.ppu.write(dst, val);
+                }
+            }
+            self.tick_peripherals(interrupts);
+        }
+    }
+
+    // the timer and OAM DMA run at the CPU speed,
+    // the PPU and APU keep the normal speed in CGB double speed mode
+    fn tick_peripherals(&mut self, interrupts: &mut Interrupts) {
+        let clocks = if self.double_speed {
+            M_CYCLE_CLOCK / 2
+        } else {
+            M_CYCLE_CLOCK
+        };
+        self.clocks += clocks;
         self.timer.emulate_cycle(interrupts);
-        self.apu.emulate_cycle();
+        self.apu.emulate_cycle(clocks as u8);
         if let Some(addr) = self.ppu.oam_dma {
-            self.ppu.oam_dma_emulate_cycle(self.read(interrupts, addr));
-            // TODO: 実装があっているか不明かつ、ppuに処理を移動したい
-            // for i in 0..0xA0 {
-            //     let data = self.bus.read(&self.cpu.interrupts, addr + i);
-            //     self.bus.ppu.write_oam(0xFE00 + i, data);
-            // }
-            // self.bus.ppu.finish_oam_dma();
-        }
-        if self.ppu.emulate_cycle(interrupts) {
+            // 0xE000-0xFFFF is mapped to WRAM for oam dma
+            let addr = if addr >= 0xE000 { addr - 0x2000 } else { addr };
+            let val = self.read_unrestricted(interrupts, addr);
+            self.log_dma_read(addr, cdl::DATA);
+            self.ppu.oam_dma_emulate_cycle(val);
+        }
+        self.ppu.oam_dma_start_cycle();
+        if self.ppu.emulate_cycle(interrupts, clocks as u8) {
             self.ppu.draw();
         }
+        if self.ppu.take_hblank() {
+            self.hdma.hblank();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cpu::Cpu, gameboy::Model};
+
+    fn dmg_bus() -> (Bus, Interrupts) {
+        let cartridge = Cartridge::with_program(&[0x11; 0x10], false);
+        let bus = Bus::new(BootRom::none(), cartridge, None, None, false);
+        (bus, Interrupts::default())
+    }
+
+    // 160 bytes at addr, 1 to 160 plus offset
+    fn fill(bus: &mut Bus, interrupts: &mut Interrupts, addr: u16, offset: u8) {
+        for i in 0..0xA0 {
+            bus.write(interrupts, addr + i, (i as u8 + 1).wrapping_add(offset));
+        }
+    }
+
+    // the M-cycle writing to the DMA register
+    fn start_oam_dma(bus: &mut Bus, interrupts: &mut Interrupts, src: u8) {
+        bus.write(interrupts, 0xFF46, src);
+        bus.tick(interrupts);
+    }
+
+    fn oam(bus: &Bus) -> Vec<u8> {
+        bus.ppu
+            .sprites()
+            .iter()
+            .flat_map(|s| [s.y, s.x, s.tile_idx, s.flags])
+            .collect()
+    }
+
+    #[test]
+    fn oam_dma_setup_delay() {
+        let (mut bus, mut interrupts) = dmg_bus();
+        fill(&mut bus, &mut interrupts, 0xC000, 0);
+        start_oam_dma(&mut bus, &mut interrupts, 0xC0);
+        // setup M-cycle: the CPU still has the bus
+        assert_eq!(bus.read(&interrupts, 0xC000), 1);
+        assert_eq!(bus.read(&interrupts, 0xFE00), 0);
+        bus.tick(&mut interrupts);
+        // then 160 M-cycles of transfer
+        for i in 0..0xA0 {
+            assert_eq!(bus.read(&interrupts, 0xFE00), 0xFF);
+            assert_eq!(bus.read(&interrupts, 0xC000), if i == 0 { 0xFF } else { i });
+            bus.tick(&mut interrupts);
+        }
+        assert_eq!(bus.read(&interrupts, 0xC000), 1);
+        let expected: Vec<u8> = (1..=0xA0).collect();
+        assert_eq!(oam(&bus), expected);
+        assert_eq!(bus.read(&interrupts, 0xFE9F), 0xA0);
+    }
+
+    #[test]
+    fn oam_dma_restart() {
+        let (mut bus, mut interrupts) = dmg_bus();
+        fill(&mut bus, &mut interrupts, 0xC000, 0);
+        fill(&mut bus, &mut interrupts, 0xC100, 0x80);
+        start_oam_dma(&mut bus, &mut interrupts, 0xC0);
+        for _ in 0..1 + 10 {
+            bus.tick(&mut interrupts);
+        }
+        // the running transfer continues through the write and the setup M-cycles
+        start_oam_dma(&mut bus, &mut interrupts, 0xC1);
+        bus.tick(&mut interrupts);
+        assert_eq!(oam(&bus)[..13], [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 0]);
+        for _ in 0..0xA0 {
+            assert!(bus.ppu.oam_dma.is_some());
+            bus.tick(&mut interrupts);
+        }
+        assert!(bus.ppu.oam_dma.is_none());
+        let expected: Vec<u8> = (0x81..=0xFF).chain(0..=0x20).collect();
+        assert_eq!(oam(&bus), expected);
+    }
+
+    #[test]
+    fn oam_dma_from_echo_ram() {
+        let (mut bus, mut interrupts) = dmg_bus();
+        fill(&mut bus, &mut interrupts, 0xC000, 0);
+        // 0xE000-0xFFFF reads WRAM, not the I/O registers and HRAM above 0xFE00
+        start_oam_dma(&mut bus, &mut interrupts, 0xE0);
+        for _ in 0..1 + 0xA0 {
+            bus.tick(&mut interrupts);
+        }
+        let expected: Vec<u8> = (1..=0xA0).collect();
+        assert_eq!(oam(&bus), expected);
+    }
+
+    #[test]
+    fn cpu_reads_during_oam_dma() {
+        let (mut bus, mut interrupts) = dmg_bus();
+        fill(&mut bus, &mut interrupts, 0xC000, 0);
+        fill(&mut bus, &mut interrupts, 0x8000, 0x40);
+        bus.write(&mut interrupts, 0xFF80, 0x22);
+        // from WRAM: the external bus sees the transferred byte, VRAM and HRAM are free
+        start_oam_dma(&mut bus, &mut interrupts, 0xC0);
+        for _ in 0..1 + 4 {
+            bus.tick(&mut interrupts);
+        }
+        assert_eq!(bus.read(&interrupts, 0x0100), 4);
+        assert_eq!(bus.read(&interrupts, 0xD000), 4);
+        assert_eq!(bus.read(&interrupts, 0x8000), 0x41);
+        assert_eq!(bus.read(&interrupts, 0xFF80), 0x22);
+        assert_eq!(bus.read(&interrupts, 0xFE00), 0xFF);
+        // writes to the busy bus are lost
+        bus.write(&mut interrupts, 0xC000, 0x33);
+        bus.write(&mut interrupts, 0x8000, 0x55);
+        while bus.ppu.oam_dma.is_some() {
+            bus.tick(&mut interrupts);
+        }
+        assert_eq!(bus.read(&interrupts, 0xC000), 1);
+        assert_eq!(bus.read(&interrupts, 0x8000), 0x55);
+
+        // from VRAM: the other way around
+        start_oam_dma(&mut bus, &mut interrupts, 0x80);
+        for _ in 0..1 + 4 {
+            bus.tick(&mut interrupts);
+        }
+        assert_eq!(bus.read(&interrupts, 0x8000), 0x44);
+        assert_eq!(bus.read(&interrupts, 0x0100), 0x11);
+        assert_eq!(bus.read(&interrupts, 0xC000), 1);
+    }
+
+    #[test]
+    fn general_purpose_dma_stalls_the_cpu() {
+        let data: Vec<u8> = (1..=0x20).collect();
+        let cartridge = Cartridge::with_program(&data, true);
+        let mut bus = Bus::new(BootRom::none(), cartridge, None, None, true);
+        let mut interrupts = Interrupts::default();
+        // 0x0100 to 0x8800, 2 blocks
+        for (addr, val) in [
+            (0xFF51, 0x01),
+            (0xFF52, 0x00),
+            (0xFF53, 0x08),
+            (0xFF54, 0x00),
+        ] {
+            bus.write(&mut interrupts, addr, val);
+        }
+        bus.write(&mut interrupts, 0xFF55, 0x01);
+        bus.tick(&mut interrupts);
+        // the M-cycle of the write, then 2 bytes per M-cycle
+        assert_eq!(bus.clocks, (1 + 0x10) * M_CYCLE_CLOCK);
+        let vram: Vec<u8> = (0x8800..0x8820)
+            .map(|addr| bus.read(&interrupts, addr))
+            .collect();
+        assert_eq!(vram, data);
+        assert_eq!(bus.read(&interrupts, 0xFF55), 0xFF);
+    }
+
+    fn cgb_bus() -> (Bus, Interrupts) {
+        let cartridge = Cartridge::with_program(&[], true);
+        let bus = Bus::new(BootRom::none(), cartridge, None, None, true);
+        (bus, Interrupts::default())
+    }
+
+    #[test]
+    fn vram_and_wram_banks() {
+        let (mut bus, mut interrupts) = cgb_bus();
+        bus.write(&mut interrupts, 0x8000, 0xAA);
+        bus.write(&mut interrupts, VBK_ADDR, 0xFF);
+        assert_eq!(bus.read(&interrupts, VBK_ADDR), 0xFF);
+        assert_eq!(bus.read(&interrupts, 0x8000), 0x00);
+        bus.write(&mut interrupts, 0x8000, 0xBB);
+        bus.write(&mut interrupts, VBK_ADDR, 0x00);
+        assert_eq!(bus.read(&interrupts, VBK_ADDR), 0xFE);
+        assert_eq!(bus.read(&interrupts, 0x8000), 0xAA);
+
+        // SVBK 0 maps bank 1 to 0xD000-0xDFFF, 0xC000-0xCFFF is always bank 0
+        bus.write(&mut interrupts, 0xC000, 0x10);
+        bus.write(&mut interrupts, 0xD000, 0x11);
+        bus.write(&mut interrupts, SVBK_ADDR, 0x01);
+        assert_eq!(bus.read(&interrupts, 0xD000), 0x11);
+        bus.write(&mut interrupts, SVBK_ADDR, 0xFA);
+        assert_eq!(bus.read(&interrupts, SVBK_ADDR), 0xFA);
+        assert_eq!(bus.read(&interrupts, 0xD000), 0x00);
+        bus.write(&mut interrupts, 0xD000, 0x12);
+        // echo RAM follows the bank
+        assert_eq!(bus.read(&interrupts, 0xF000), 0x12);
+        assert_eq!(bus.read(&interrupts, 0xC000), 0x10);
+        bus.write(&mut interrupts, SVBK_ADDR, 0x00);
+        assert_eq!(bus.read(&interrupts, SVBK_ADDR), 0xF8);
+        assert_eq!(bus.read(&interrupts, 0xD000), 0x11);
+    }
+
+    #[test]
+    fn palette_index_auto_increment() {
+        let (mut bus, mut interrupts) = cgb_bus();
+        // BCPS: auto-increment from 0x3E, wrapping to 0
+        bus.write(&mut interrupts, 0xFF68, 0x80 | 0x3E);
+        for val in [0x01, 0x02, 0x03] {
+            bus.write(&mut interrupts, 0xFF69, val);
+        }
+        assert_eq!(bus.read(&interrupts, 0xFF68), 0xC0 | 0x01);
+        for (index, val) in [(0x3E, 0x01), (0x3F, 0x02), (0x00, 0x03)] {
+            bus.write(&mut interrupts, 0xFF68, index);
+            // reads do not increment
+            assert_eq!(bus.read(&interrupts, 0xFF69), val);
+            assert_eq!(bus.read(&interrupts, 0xFF69), val);
+        }
+
+        // OCPS without auto-increment: every write goes to the same index
+        bus.write(&mut interrupts, 0xFF6A, 0x05);
+        bus.write(&mut interrupts, 0xFF6B, 0x04);
+        bus.write(&mut interrupts, 0xFF6B, 0x05);
+        assert_eq!(bus.read(&interrupts, 0xFF6A), 0x45);
+        assert_eq!(bus.read(&interrupts, 0xFF6B), 0x05);
+        bus.write(&mut interrupts, 0xFF6A, 0x06);
+        assert_eq!(bus.read(&interrupts, 0xFF6B), 0xFF);
+    }
+
+    #[test]
+    fn skip_bootrom_registers() {
+        // push af; push bc; push de; push hl
+        let program = [0xF5, 0xC5, 0xD5, 0xE5];
+        for (model, expected) in [
+            (Model::Dmg, [0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D]),
+            (Model::Cgb, [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D]),
+        ] {
+            let cgb = model == Model::Cgb;
+            let cartridge = Cartridge::with_program(&program, cgb);
+            let mut bus = Bus::new(BootRom::none(), cartridge, None, None, cgb);
+            let mut cpu = Cpu::new();
+            cpu.skip_bootrom(model);
+            bus.skip_bootrom();
+            for _ in 0..5 {
+                cpu.emulate_cycle(&mut bus);
+            }
+            // a, f, b, c, d, e, h, l pushed below SP 0xFFFE
+            let registers: Vec<u8> = (0xFFF6..=0xFFFD)
+                .rev()
+                .map(|addr| bus.read(&cpu.interrupts, addr))
+                .collect();
+            assert_eq!(registers, expected, "cgb {}", cgb);
+            assert_eq!(bus.read(&cpu.interrupts, 0xFF40), 0x91);
+            assert_eq!(bus.read(&cpu.interrupts, 0xFF26) & 0x80, 0x80);
+        }
+    }
+
+    #[test]
+    fn double_speed_timer() {
+        let (mut normal, mut interrupts) = cgb_bus();
+        let (mut double, _) = cgb_bus();
+        double.write(&mut interrupts, KEY1_ADDR, 0x01);
+        assert!(double.switch_speed());
+        assert_eq!(double.read(&interrupts, KEY1_ADDR), 0xFE);
+        for bus in [&mut normal, &mut double] {
+            bus.write(&mut interrupts, 0xFF40, 0x91);
+            // sound on, channel 2: length 2, DAC on, triggered with the length enabled
+            bus.write(&mut interrupts, 0xFF26, 0x80);
+            bus.write(&mut interrupts, 0xFF16, 0x3E);
+            bus.write(&mut interrupts, 0xFF17, 0xF0);
+            bus.write(&mut interrupts, 0xFF19, 0xC0);
+        }
+        // 4 frames, the PPU and APU see the same T-cycles in both speeds
+        let mut channel_off = false;
+        while normal.clocks < 70224 * 4 {
+            normal.tick(&mut interrupts);
+            while double.clocks < normal.clocks {
+                double.tick(&mut interrupts);
+            }
+            for addr in [0xFF41, 0xFF44, 0xFF26] {
+                assert_eq!(
+                    normal.read(&interrupts, addr),
+                    double.read(&interrupts, addr),
+                    "{:04X} at {}",
+                    addr,
+                    normal.clocks
+                );
+            }
+            channel_off |= normal.read(&interrupts, 0xFF26) & 0x02 == 0;
+        }
+        assert!(channel_off);
+        // the first frame after turning the LCD on is not shown
+        assert_eq!((normal.ppu.frames, double.ppu.frames), (3, 3));
+        // and the timer twice as many
+        let div = |bus: &Bus| bus.read(&interrupts, 0xFF04) as u64;
+        assert_eq!(div(&normal), (70224 * 4 / 256) & 0xFF);
+        assert_eq!(div(&double), (70224 * 4 * 2 / 256) & 0xFF);
     }
 }
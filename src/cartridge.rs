@@ -7,6 +7,7 @@ pub struct Cartridge {
     rom: Box<[u8]>,
     sram: Box<[u8]>,
     mbc: Mbc,
+    header: CartridgeHeader,
 }
 
 impl Cartridge {
@@ -17,14 +18,15 @@ impl Cartridge {
         let sram_size = header.sram_size();
         let mbc = Mbc::new(header.cartridge_type, rom_size >> 14); // rom bank is 16 KiB
         println!(
-            "title: {}, type: {}, rom_size: {} B, sram_size: {} B",
+            "title: {}, type: {}, rom_size: {} B, sram_size: {} B, cgb: {}",
             title,
             match mbc {
                 Mbc::NoMbc => "ROM ONLY",
                 Mbc::Mbc1 { .. } => "MBC1",
             },
             rom_size,
-            sram_size
+            sram_size,
+            header.supports_cgb()
         );
         assert_eq!(rom.len(), rom_size, "invalid rom size");
 
@@ -32,12 +34,25 @@ impl Cartridge {
             rom,
             sram: vec![0; sram_size].into(),
             mbc,
+            header,
         }
     }
 
+    pub fn is_cgb(&self) -> bool {
+        self.header.supports_cgb()
+    }
+
+    pub fn supports_sgb(&self) -> bool {
+        self.header.supports_sgb()
+    }
+
+    pub fn compat_palette_key(&self) -> Option<(u8, u8)> {
+        self.header.compat_palette_key()
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x7FFF => self.rom[self.mbc.get_addr(addr) & self.rom.len() - 1],
+            0x0000..=0x7FFF => self.rom[self.mbc.get_addr(addr) & (self.rom.len() - 1)],
             0xA000..=0xBFFF => match self.mbc {
                 Mbc::NoMbc => self.sram[addr as usize & (self.sram.len() - 1)],
                 Mbc::Mbc1 {
@@ -54,6 +69,15 @@ impl Cartridge {
         }
     }
 
+    pub fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    // physical ROM offset mapped at addr (0x0000-0x7FFF)
+    pub fn rom_offset(&self, addr: u16) -> usize {
+        self.mbc.get_addr(addr) & (self.rom.len() - 1)
+    }
+
     pub fn write(&mut self, addr: u16, val: u8) {
         match addr {
             0x0000..=0x7FFF => self.mbc.write(addr, val),
@@ -71,3 +95,21 @@ impl Cartridge {
         }
     }
 }
+
+#[cfg(test)]
+impl Cartridge {
+    // 32 KiB ROM without MBC, program at the entry point 0x0100
+    pub fn with_program(program: &[u8], cgb: bool) -> Self {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        if cgb {
+            rom[0x143] = 0x80;
+        }
+        let mut checksum = 0u8;
+        for &byte in &rom[0x134..=0x14C] {
+            checksum = checksum.wrapping_sub(byte).wrapping_sub(1);
+        }
+        rom[0x14D] = checksum;
+        Self::new(rom.into())
+    }
+}
@@ -21,12 +21,39 @@ impl CartridgeHeader {
     pub fn new(data: [u8; 0x50]) -> Self {
         let ret = unsafe { std::mem::transmute::<[u8; 0x50], Self>(data) };
         let mut checksum = 0u8;
-        for i in 0x34..=0x4C {
-            checksum = checksum.wrapping_sub(data[i]).wrapping_sub(1);
+        for &byte in &data[0x34..=0x4C] {
+            checksum = checksum.wrapping_sub(byte).wrapping_sub(1);
         }
         assert_eq!(checksum, ret.header_checksum, "invalid header checksum");
         ret
     }
+    // 0x80: CGB enhanced (DMG compatible), 0xC0: CGB only
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    // SGB functions are enabled only for games with both the SGB flag and the new licensee code
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee_code == 0x33
+    }
+
+    // the CGB boot ROM colorizes DMG games licensed by Nintendo,
+    // keyed on the sum of the title bytes (0x0134-0x0143) and the 4th letter of the title
+    pub fn compat_palette_key(&self) -> Option<(u8, u8)> {
+        let nintendo = self.old_licensee_code == 0x01
+            || (self.old_licensee_code == 0x33 && self.new_licensee_code == *b"01");
+        if !nintendo {
+            return None;
+        }
+        let checksum = self
+            .title
+            .iter()
+            .chain(&self.maker_code)
+            .chain([&self.cgb_flag])
+            .fold(0u8, |sum, &b| sum.wrapping_add(b));
+        Some((checksum, self.title[3]))
+    }
+
     pub fn rom_size(&self) -> usize {
         assert!(
             self.rom_size <= 0x08,
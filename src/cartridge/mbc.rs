@@ -13,7 +13,7 @@ impl Mbc {
     pub fn new(cartridge_type: u8, rom_banks: usize) -> Self {
         match cartridge_type {
             0x00 | 0x08 | 0x09 => Self::NoMbc,
-            0x01 | 0x02 | 0x03 => Self::Mbc1 {
+            0x01..=0x03 => Self::Mbc1 {
                 sram_enable: false,
                 low_bank: 1,
                 high_bank: 0,
@@ -0,0 +1,176 @@
+use std::{fs, io, path::Path};
+
+// code/data log: one flag byte per physical ROM byte
+pub const OPCODE: u8 = 1 << 0; // executed as the first byte of an instruction
+pub const OPERAND: u8 = 1 << 1; // read as a part of an instruction (immediate, CB opcode)
+pub const DATA: u8 = 1 << 2; // read as data
+pub const GRAPHICS: u8 = 1 << 3; // copied into VRAM tile data
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+pub struct Cdl {
+    flags: Box<[u8]>,
+    // last ROM data read by the CPU, for detecting CPU copies to VRAM
+    last_data: Option<(usize, u8)>,
+}
+
+impl Cdl {
+    pub fn new(rom_size: usize) -> Self {
+        Self {
+            flags: vec![0; rom_size].into(),
+            last_data: None,
+        }
+    }
+
+    // merge a previously saved log, so coverage accumulates across sessions
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let data = fs::read(path)?;
+        if data.len() != self.flags.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "CDL size {} B does not match ROM size {} B",
+                    data.len(),
+                    self.flags.len()
+                ),
+            ));
+        }
+        for (flag, val) in self.flags.iter_mut().zip(data) {
+            *flag |= val;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, &self.flags)
+    }
+
+    // DMA reads, the source of a VRAM DMA is tagged GRAPHICS directly
+    pub fn log(&mut self, offset: usize, flag: u8) {
+        self.flags[offset] |= flag;
+    }
+
+    pub fn log_cpu_read(&mut self, offset: usize, flag: u8, val: u8) {
+        self.flags[offset] |= flag;
+        if flag == DATA {
+            self.last_data = Some((offset, val));
+        }
+    }
+
+    // CPU write to VRAM tile data.
+    // heuristic: the last ROM byte read as data is tagged GRAPHICS if the written value
+    // matches it, which catches `ld a, [hl+]; ld [de], a` style copy loops. bytes
+    // transformed on the way (decompression) are missed, and a matching value written
+    // by other code (a fill reusing a value read from ROM) is tagged as well.
+    // each read is matched against one write at most.
+    pub fn log_vram_write(&mut self, val: u8) {
+        if let Some((offset, data)) = self.last_data.take() {
+            if data == val {
+                self.flags[offset] |= GRAPHICS;
+            }
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let count = |flags: &[u8], flag: u8| flags.iter().filter(|&&f| f & flag != 0).count();
+        let covered = |flags: &[u8]| flags.iter().filter(|&&f| f != 0).count();
+        let total = self.flags.len();
+        let mut ret = format!(
+            "ROM coverage: {} / {} B ({:.2}%)\n",
+            covered(&self.flags),
+            total,
+            covered(&self.flags) as f64 * 100.0 / total as f64
+        );
+        for (name, flag) in [
+            ("opcode", OPCODE),
+            ("operand", OPERAND),
+            ("data", DATA),
+            ("graphics", GRAPHICS),
+        ] {
+            ret.push_str(&format!(
+                "  {:<8} {:>8} B ({:.2}%)\n",
+                name,
+                count(&self.flags, flag),
+                count(&self.flags, flag) as f64 * 100.0 / total as f64
+            ));
+        }
+        ret.push_str("per bank:\n");
+        for (bank, flags) in self.flags.chunks(ROM_BANK_SIZE).enumerate() {
+            ret.push_str(&format!(
+                "  ${:02X} {:>6} B ({:>6.2}%) code {:>6} B, data {:>6} B, graphics {:>6} B\n",
+                bank,
+                covered(flags),
+                covered(flags) as f64 * 100.0 / flags.len() as f64,
+                count(flags, OPCODE | OPERAND),
+                count(flags, DATA),
+                count(flags, GRAPHICS)
+            ));
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn log() {
+        let mut cdl = Cdl::new(0x8000);
+        cdl.log_cpu_read(0x0100, OPCODE, 0x00);
+        cdl.log_cpu_read(0x0101, OPERAND, 0x50);
+        cdl.log_cpu_read(0x0101, DATA, 0x50);
+        cdl.log(0x4000, GRAPHICS);
+        assert_eq!(cdl.flags[0x0100], 0b0001);
+        assert_eq!(cdl.flags[0x0101], 0b0110);
+        assert_eq!(cdl.flags[0x4000], 0b1000);
+        assert_eq!(cdl.flags.iter().filter(|&&f| f != 0).count(), 3);
+    }
+
+    #[test]
+    fn cpu_copy_to_vram() {
+        let mut cdl = Cdl::new(0x8000);
+        cdl.log_cpu_read(0x2000, DATA, 0x3C);
+        cdl.log_vram_write(0x3C);
+        // a read is matched against one write only
+        cdl.log_vram_write(0x3C);
+        cdl.log_cpu_read(0x2001, DATA, 0x7E);
+        cdl.log_vram_write(0x00);
+        // DMA reads do not take part in the detection
+        cdl.log(0x2002, DATA);
+        cdl.log_vram_write(0x00);
+        assert_eq!(cdl.flags[0x2000], DATA | GRAPHICS);
+        assert_eq!(cdl.flags[0x2001], DATA);
+        assert_eq!(cdl.flags[0x2002], DATA);
+    }
+
+    #[test]
+    fn load_merges() {
+        let path = temp_path("merge.cdl");
+        let mut saved = Cdl::new(0x8000);
+        saved.log(0x0000, OPCODE);
+        saved.log(0x0001, DATA);
+        saved.save(&path).unwrap();
+
+        let mut cdl = Cdl::new(0x8000);
+        cdl.log(0x0001, GRAPHICS);
+        cdl.log(0x0002, OPERAND);
+        cdl.load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(&cdl.flags[..4], [OPCODE, DATA | GRAPHICS, OPERAND, 0]);
+    }
+
+    #[test]
+    fn load_size_mismatch() {
+        let path = temp_path("mismatch.cdl");
+        Cdl::new(0x4000).save(&path).unwrap();
+        let mut cdl = Cdl::new(0x8000);
+        cdl.log(0x0000, OPCODE);
+        let err = cdl.load(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // the log is left untouched
+        assert_eq!(cdl.flags[0], OPCODE);
+    }
+}
@@ -3,11 +3,15 @@ mod fetch;
 mod instruction;
 pub mod interrupt;
 mod operand;
+pub mod profiler;
 mod registers;
 
 use self::interrupt::{Interrupts, JOYPAD, LCD_STAT, SERIAL, TIMER, VBLANK};
+use self::profiler::{Profiler, Routine};
 use self::registers::Registers;
 use crate::bus::Bus;
+use crate::cdl;
+use crate::gameboy::Model;
 
 #[derive(Default)]
 struct Ctx {
@@ -16,12 +20,34 @@ struct Ctx {
     interrupt: bool,
 }
 
+// what to do when the CPU executes one of the undefined opcodes
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum IllegalOpcodePolicy {
+    LockUp, // hang the CPU until reset like the hardware, the rest of the system keeps running
+    Break,  // lock up and pause the emulation
+    Panic,
+}
+
+// reported when the CPU executes an undefined opcode
+#[derive(Clone, Copy, Debug)]
+pub struct IllegalOpcode {
+    pub opcode: u8,
+    pub addr: u16,
+    pub bank: Option<usize>,
+}
+
 pub struct Cpu {
     registers: Registers,
     pub interrupts: Interrupts,
     halting: bool,
+    halt_bug: bool,
+    stopping: bool,
+    locked: bool,
     ei_delay: bool,
     ctx: Ctx,
+    pub profiler: Option<Profiler>,
+    pub illegal_opcode_policy: IllegalOpcodePolicy,
+    illegal_opcode: Option<IllegalOpcode>,
 }
 
 impl Cpu {
@@ -30,12 +56,30 @@ impl Cpu {
             registers: Registers::default(),
             interrupts: Interrupts::default(),
             halting: false,
+            halt_bug: false,
+            stopping: false,
+            locked: false,
             ei_delay: false,
             ctx: Ctx::default(),
+            profiler: None,
+            illegal_opcode_policy: IllegalOpcodePolicy::LockUp,
+            illegal_opcode: None,
         }
     }
     pub fn emulate_cycle(&mut self, bus: &mut Bus) {
-        if self.ctx.interrupt {
+        if self.locked {
+            // no more instructions and interrupts, but the clock keeps running
+            self.tick(bus);
+            return;
+        }
+
+        if self.stopping {
+            // system clock is stopped until a selected joypad input goes low
+            if !bus.joypad.is_input_low() {
+                return;
+            }
+            self.stopping = false;
+        } else if self.ctx.interrupt {
             self.call_isr(bus);
         } else {
             self.decode(bus);
@@ -46,6 +90,10 @@ impl Cpu {
             return;
         }
 
+        if self.stopping || self.locked {
+            return;
+        }
+
         // fetch / execute overlap
         self.fetch(bus);
 
@@ -56,12 +104,64 @@ impl Cpu {
         }
     }
 
+    // registers as left by the boot ROM
+    pub fn skip_bootrom(&mut self, model: Model) {
+        let [a, f, b, c, d, e, h, l] = match model {
+            Model::Dmg => [0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Sgb => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::Cgb => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+        };
+        self.registers = Registers {
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        };
+    }
+
+    // last illegal opcode executed, for tooling
+    pub fn take_illegal_opcode(&mut self) -> Option<IllegalOpcode> {
+        self.illegal_opcode.take()
+    }
+
+    // debugging only, the hardware stays locked up until reset:
+    // continue with the byte after the illegal opcode
+    pub fn skip_illegal_opcode(&mut self, bus: &mut Bus) {
+        if self.locked {
+            self.locked = false;
+            self.fetch(bus);
+        }
+    }
+
+    // interrupt dispatch takes 5 M-cycles: the discarded fetch, sp decrement,
+    // push pc (upper, lower) and jump to the handler
+    // https://gekkio.fi/files/gb-docs/gbctr.pdf
     fn call_isr(&mut self, bus: &mut Bus) {
-        self.push16(bus, self.registers.pc);
-        let highest_interrupt = 1 << self.interrupts.get_interrupt().trailing_zeros();
+        self.interrupts.ime = false;
+        self.tick(bus);
+        let [lo, hi] = u16::to_le_bytes(self.registers.pc);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write_bus(bus, self.registers.sp, hi);
+        // the interrupt is chosen after pushing the upper byte, so a push overwriting IE
+        // can redirect the dispatch to another interrupt or cancel it (jump to 0x0000)
+        let interrupt = self.interrupts.get_interrupt();
+        let highest_interrupt = if interrupt == 0 {
+            0
+        } else {
+            1 << interrupt.trailing_zeros()
+        };
         self.interrupts.interrupt_flags &= !highest_interrupt;
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.write_bus(bus, self.registers.sp, lo);
         // cal isr
         self.registers.pc = match highest_interrupt {
+            0 => 0x0000,
             VBLANK => 0x0040,
             LCD_STAT => 0x0048,
             TIMER => 0x0050,
@@ -69,16 +169,42 @@ impl Cpu {
             JOYPAD => 0x0060,
             _ => panic!("invalid interrupt: {:02X}", highest_interrupt),
         };
+        self.profile_call(bus);
+        self.tick(bus);
 
         self.ctx.interrupt = false;
     }
 
     fn tick(&mut self, bus: &mut Bus) {
         bus.tick(&mut self.interrupts);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.tick(bus.rom_bank(self.registers.pc));
+        }
+    }
+
+    // shadow call stack: called after jumping to the callee
+    fn profile_call(&mut self, bus: &Bus) {
+        if let Some(profiler) = &mut self.profiler {
+            let pc = self.registers.pc;
+            profiler.call(Routine::new(bus.rom_bank(pc), pc), self.registers.sp);
+        }
+    }
+
+    // shadow call stack: called before popping the return address
+    fn profile_ret(&mut self) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.ret(self.registers.sp);
+        }
     }
 
     fn read_bus(&mut self, bus: &mut Bus, addr: u16) -> u8 {
+        self.read_bus_as(bus, addr, cdl::DATA)
+    }
+
+    // flag: how the byte is used, for the code/data log
+    fn read_bus_as(&mut self, bus: &mut Bus, addr: u16, flag: u8) -> u8 {
         let val = bus.read(&self.interrupts, addr);
+        bus.log_read(addr, flag, val);
         self.tick(bus);
         val
     }
@@ -88,3 +214,118 @@ impl Cpu {
         self.tick(bus);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bootrom::BootRom, cartridge::Cartridge, gameboy::M_CYCLE_CLOCK};
+
+    const DIV: u16 = 0xFF04;
+    const KEY1: u16 = 0xFF4D;
+
+    fn system(program: &[u8], cgb: bool) -> (Cpu, Bus) {
+        let mut bootrom = BootRom::new(vec![0; 0x100].into());
+        // unmapped, as after the boot ROM hands over to the cartridge
+        bootrom.write(0xFF50, 1);
+        let cartridge = Cartridge::with_program(program, cgb);
+        let bus = Bus::new(bootrom, cartridge, None, None, cgb);
+        let mut cpu = Cpu::new();
+        cpu.registers.pc = 0x0100;
+        (cpu, bus)
+    }
+
+    // one instruction per call, starting with the NOP left in the fetch stage
+    fn step(cpu: &mut Cpu, bus: &mut Bus, instructions: usize) {
+        for _ in 0..instructions {
+            cpu.emulate_cycle(bus);
+        }
+    }
+
+    #[test]
+    fn halt_bug() {
+        // di; halt; inc a; nop
+        let (mut cpu, mut bus) = system(&[0xF3, 0x76, 0x3C, 0x00], false);
+        cpu.registers.a = 0;
+        cpu.interrupts.interrupt_enable = VBLANK;
+        cpu.interrupts.interrupt_flags = VBLANK;
+        step(&mut cpu, &mut bus, 3);
+        // halt exits immediately without incrementing pc, the byte after it is read twice
+        assert!(!cpu.halting);
+        assert_eq!(cpu.registers.pc, 0x0102);
+        step(&mut cpu, &mut bus, 2);
+        assert_eq!(cpu.registers.a, 2);
+        assert_eq!(cpu.registers.pc, 0x0104);
+        // no dispatch with IME=0
+        assert_eq!(cpu.interrupts.interrupt_flags & VBLANK, VBLANK);
+    }
+
+    #[test]
+    fn halt_wakes_up_without_ime() {
+        // di; halt; inc a; nop
+        let (mut cpu, mut bus) = system(&[0xF3, 0x76, 0x3C, 0x00], false);
+        cpu.registers.a = 0;
+        cpu.interrupts.interrupt_enable = TIMER;
+        step(&mut cpu, &mut bus, 3);
+        assert!(cpu.halting);
+        step(&mut cpu, &mut bus, 10);
+        assert!(cpu.halting);
+        cpu.interrupts.interrupt_flags |= TIMER;
+        step(&mut cpu, &mut bus, 2);
+        // execution continues after halt, inc a runs once
+        assert!(!cpu.halting);
+        assert_eq!(cpu.registers.a, 1);
+        assert_eq!(cpu.registers.pc, 0x0104);
+    }
+
+    #[test]
+    fn skip_illegal_opcode() {
+        // inc a; illegal; inc a
+        let (mut cpu, mut bus) = system(&[0x3C, 0xD3, 0x3C], false);
+        cpu.registers.a = 0;
+        step(&mut cpu, &mut bus, 3);
+        assert!(cpu.locked);
+        let illegal = cpu.take_illegal_opcode().unwrap();
+        assert_eq!((illegal.opcode, illegal.addr), (0xD3, 0x0101));
+        step(&mut cpu, &mut bus, 10);
+        assert_eq!(cpu.registers.a, 1);
+        cpu.skip_illegal_opcode(&mut bus);
+        step(&mut cpu, &mut bus, 1);
+        assert!(!cpu.locked);
+        assert_eq!(cpu.registers.a, 2);
+        assert_eq!(cpu.registers.pc, 0x0104);
+    }
+
+    #[test]
+    fn stop_speed_switch() {
+        // stop; nop; inc a
+        let (mut cpu, mut bus) = system(&[0x10, 0x00, 0x3C], true);
+        cpu.registers.a = 0;
+        bus.write(&mut cpu.interrupts, KEY1, 0x01);
+        step(&mut cpu, &mut bus, 1);
+        let clocks = bus.clocks;
+        step(&mut cpu, &mut bus, 1);
+        // 2-byte opcode, stalled for the speed switch, then the fetch at double speed
+        assert!(!cpu.stopping);
+        assert_eq!(cpu.registers.pc, 0x0103);
+        assert_eq!(
+            bus.clocks - clocks,
+            (instruction::SPEED_SWITCH_CYCLES as u128 + 1) * M_CYCLE_CLOCK / 2
+        );
+        assert_eq!(bus.read(&cpu.interrupts, KEY1), 0xFE);
+        step(&mut cpu, &mut bus, 1);
+        assert_eq!(cpu.registers.a, 1);
+    }
+
+    #[test]
+    fn stop_without_speed_switch() {
+        // stop; nop; inc a
+        let (mut cpu, mut bus) = system(&[0x10, 0x00, 0x3C], false);
+        step(&mut cpu, &mut bus, 2);
+        assert!(cpu.stopping);
+        assert_eq!(cpu.registers.pc, 0x0102);
+        // the system clock is stopped until a joypad input goes low, DIV would tick every 64 M-cycles
+        step(&mut cpu, &mut bus, 100);
+        assert!(cpu.stopping);
+        assert_eq!(bus.read(&cpu.interrupts, DIV), 0);
+    }
+}
@@ -24,8 +24,8 @@ impl Cpu {
             0x31 => self.ld16(bus, Reg16::SP, Imm16),
             0x02 => self.ld(bus, Indirect::BC, Reg8::A),
             0x12 => self.ld(bus, Indirect::DE, Reg8::A),
-            0x22 => self.ld(bus, Indirect::HLI, Reg8::A),
-            0x32 => self.ld(bus, Indirect::HLD, Reg8::A),
+            0x22 => self.ld(bus, Indirect::Hli, Reg8::A),
+            0x32 => self.ld(bus, Indirect::Hld, Reg8::A),
             0x03 => self.inc16(bus, Reg16::BC),
             0x13 => self.inc16(bus, Reg16::DE),
             0x23 => self.inc16(bus, Reg16::HL),
@@ -56,8 +56,8 @@ impl Cpu {
             0x39 => self.add_hl_reg16(bus, Reg16::SP),
             0x0A => self.ld(bus, Reg8::A, Indirect::BC),
             0x1A => self.ld(bus, Reg8::A, Indirect::DE),
-            0x2A => self.ld(bus, Reg8::A, Indirect::HLI),
-            0x3A => self.ld(bus, Reg8::A, Indirect::HLD),
+            0x2A => self.ld(bus, Reg8::A, Indirect::Hli),
+            0x3A => self.ld(bus, Reg8::A, Indirect::Hld),
             0x0B => self.dec16(bus, Reg16::BC),
             0x1B => self.dec16(bus, Reg16::DE),
             0x2B => self.dec16(bus, Reg16::HL),
@@ -208,16 +208,16 @@ impl Cpu {
             0xBF => self.cp(bus, Reg8::A),
             0xC0 => self.ret_c(bus, Cond::NZ),
             0xD0 => self.ret_c(bus, Cond::NC),
-            0xE0 => self.ld(bus, Direct8::DFF, Reg8::A),
-            0xF0 => self.ld(bus, Reg8::A, Direct8::DFF),
+            0xE0 => self.ld(bus, Direct8::Dff, Reg8::A),
+            0xF0 => self.ld(bus, Reg8::A, Direct8::Dff),
             0xC1 => self.pop(bus, Reg16::BC),
             0xD1 => self.pop(bus, Reg16::DE),
             0xE1 => self.pop(bus, Reg16::HL),
             0xF1 => self.pop(bus, Reg16::AF),
             0xC2 => self.jp_c(bus, Cond::NZ),
             0xD2 => self.jp_c(bus, Cond::NC),
-            0xE2 => self.ld(bus, Indirect::CFF, Reg8::A),
-            0xF2 => self.ld(bus, Reg8::A, Indirect::CFF),
+            0xE2 => self.ld(bus, Indirect::Cff, Reg8::A),
+            0xF2 => self.ld(bus, Reg8::A, Indirect::Cff),
             0xC3 => self.jp(bus),
             0xD3 => self.undefined(bus),
             0xE3 => self.undefined(bus),
@@ -1,11 +1,16 @@
 use crate::bus::Bus;
+use crate::cdl;
 use crate::cpu::Cpu;
 
 impl Cpu {
     pub fn fetch(&mut self, bus: &mut Bus) {
-        self.ctx.opcode = self.read_bus(bus, self.registers.pc);
+        self.ctx.opcode = self.read_bus_as(bus, self.registers.pc, cdl::OPCODE);
         if self.interrupts.ime && self.interrupts.get_interrupt() != 0 {
             self.ctx.interrupt = true;
+        } else if self.halt_bug {
+            // halt bug: pc is not incremented, so the byte after halt is read twice
+            self.halt_bug = false;
+            self.ctx.interrupt = false;
         } else {
             self.registers.pc = self.registers.pc.wrapping_add(1);
             self.ctx.interrupt = false;
@@ -1,24 +1,60 @@
 use crate::bus::Bus;
 use crate::cpu::operand::Imm16;
-use crate::cpu::{operand::Imm8, Cpu};
+use crate::cpu::{operand::Imm8, Cpu, IllegalOpcode, IllegalOpcodePolicy};
 
 use super::operand::{Cond, Reg16, IO16, IO8};
 
+// M-cycles the CPU is stalled for after a CGB speed switch
+pub const SPEED_SWITCH_CYCLES: usize = 2050;
+
 impl Cpu {
     pub fn nop(&mut self, bus: &mut Bus) {
         self.tick(bus)
     }
 
     // stop
-    pub fn stop(&mut self, _: &mut Bus) {
-        // omit implementation
+    // https://gbdev.io/pandocs/Reducing_Power_Consumption.html#using-the-stop-instruction
+    pub fn stop(&mut self, bus: &mut Bus) {
+        if bus.switch_speed() {
+            // CGB speed switch: 2-byte opcode, the CPU is stalled until the clock settles
+            self.registers.pc = self.registers.pc.wrapping_add(1);
+            bus.timer.reset_div();
+            for _ in 0..SPEED_SWITCH_CYCLES {
+                self.tick(bus);
+            }
+            return;
+        }
+        let interrupt_pending = self.interrupts.get_interrupt() != 0;
+        if bus.joypad.is_input_low() {
+            if !interrupt_pending {
+                // 2-byte opcode, enters halt mode
+                self.registers.pc = self.registers.pc.wrapping_add(1);
+                self.halting = true;
+            }
+            // 1-byte opcode, nothing happens if an interrupt is pending
+            return;
+        }
+        if !interrupt_pending {
+            // 2-byte opcode
+            self.registers.pc = self.registers.pc.wrapping_add(1);
+        }
+        bus.timer.reset_div();
+        // the LCD shows a blank screen while the system clock is stopped
+        bus.ppu.draw_blank();
+        self.stopping = true;
     }
 
-    pub fn halt(&mut self, _: &mut Bus) {
+    pub fn halt(&mut self, bus: &mut Bus) {
         if self.interrupts.get_interrupt() == 0 {
             self.halting = true;
-        } else {
+        } else if self.halting {
+            // waking up from halt takes 1 extra M-cycle before the next fetch or dispatch
             self.halting = false;
+            self.tick(bus);
+        } else if !self.interrupts.ime {
+            // halt bug: if IME=0 and an interrupt is already pending, halt exits immediately
+            // and fails to increment pc on the next fetch
+            self.halt_bug = true;
         }
     }
 
@@ -440,7 +476,7 @@ impl Cpu {
         Self: IO8<S>,
     {
         let val = self.read8(bus, src);
-        let result = (val << 4) | (val >> 4);
+        let result = val.rotate_right(4);
         self.registers.set_zf(result == 0);
         self.registers.set_nf(false);
         self.registers.set_hf(false);
@@ -559,6 +595,7 @@ impl Cpu {
         let val = self.read16(bus, Imm16);
         self.push16(bus, self.registers.pc);
         self.registers.pc = val;
+        self.profile_call(bus);
         self.tick(bus); // cycle +1
     }
 
@@ -569,12 +606,14 @@ impl Cpu {
         if self.cond(c) {
             self.push16(bus, self.registers.pc);
             self.registers.pc = val;
+            self.profile_call(bus);
             self.tick(bus); // cycle +1
         }
     }
 
     // return from subroutine
     pub fn ret(&mut self, bus: &mut Bus) {
+        self.profile_ret();
         let val = self.pop16(bus);
         self.registers.pc = val;
         self.tick(bus); // cycle +1
@@ -584,6 +623,7 @@ impl Cpu {
     pub fn ret_c(&mut self, bus: &mut Bus, c: Cond) {
         self.tick(bus);
         if self.cond(c) {
+            self.profile_ret();
             let val = self.pop16(bus);
             self.registers.pc = val;
             self.tick(bus); // cycle +1
@@ -600,6 +640,7 @@ impl Cpu {
     pub fn rst(&mut self, bus: &mut Bus, addr: u16) {
         self.push16(bus, self.registers.pc);
         self.registers.pc = addr;
+        self.profile_call(bus);
         self.tick(bus); // cycle +1
     }
 
@@ -627,7 +668,17 @@ impl Cpu {
         self.interrupts.ime = false;
     }
 
-    pub fn undefined(&mut self, _: &mut Bus) {
-        panic!("undefined instruction {:2X}", self.ctx.opcode);
+    pub fn undefined(&mut self, bus: &mut Bus) {
+        if self.illegal_opcode_policy == IllegalOpcodePolicy::Panic {
+            panic!("undefined instruction {:2X}", self.ctx.opcode);
+        }
+        let addr = self.registers.pc.wrapping_sub(1);
+        self.illegal_opcode = Some(IllegalOpcode {
+            opcode: self.ctx.opcode,
+            addr,
+            bank: bus.rom_bank(addr),
+        });
+        self.locked = true;
+        self.tick(bus);
     }
 }
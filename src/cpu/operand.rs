@@ -1,4 +1,5 @@
 use crate::bus::Bus;
+use crate::cdl;
 use crate::cpu::Cpu;
 
 pub trait IO8<T: Copy> {
@@ -47,16 +48,16 @@ pub enum Indirect {
     BC,
     DE,
     HL,
-    CFF,
-    HLD,
-    HLI,
+    Cff,
+    Hld,
+    Hli,
 }
 
 // read 8 bit from PC addressed register
 #[derive(Copy, Clone, Debug)]
 pub enum Direct8 {
     D,
-    DFF,
+    Dff,
 }
 
 // read 16 bit from PC addressed register
@@ -122,9 +123,9 @@ impl IO16<Reg16> for Cpu {
 
 impl IO8<Imm8> for Cpu {
     fn read8(&mut self, bus: &mut Bus, _: Imm8) -> u8 {
-        let val = self.read_bus(bus, self.registers.pc);
+        let val = self.read_bus_as(bus, self.registers.pc, cdl::OPERAND);
         self.registers.pc = self.registers.pc.wrapping_add(1);
-        return val;
+        val
     }
 
     fn write8(&mut self, _: &mut Bus, _: Imm8, _: u8) {
@@ -151,13 +152,13 @@ impl IO8<Indirect> for Cpu {
             Indirect::BC => self.read_bus(bus, self.registers.bc()),
             Indirect::DE => self.read_bus(bus, self.registers.de()),
             Indirect::HL => self.read_bus(bus, self.registers.hl()),
-            Indirect::CFF => self.read_bus(bus, 0xFF00 | u16::from(self.registers.c)),
-            Indirect::HLD => {
+            Indirect::Cff => self.read_bus(bus, 0xFF00 | u16::from(self.registers.c)),
+            Indirect::Hld => {
                 let addr = self.registers.hl();
                 self.registers.write_hl(addr.wrapping_sub(1));
                 self.read_bus(bus, addr)
             }
-            Indirect::HLI => {
+            Indirect::Hli => {
                 let addr = self.registers.hl();
                 self.registers.write_hl(addr.wrapping_add(1));
                 self.read_bus(bus, addr)
@@ -170,13 +171,13 @@ impl IO8<Indirect> for Cpu {
             Indirect::BC => self.write_bus(bus, self.registers.bc(), val),
             Indirect::DE => self.write_bus(bus, self.registers.de(), val),
             Indirect::HL => self.write_bus(bus, self.registers.hl(), val),
-            Indirect::CFF => self.write_bus(bus, 0xFF00 | u16::from(self.registers.c), val),
-            Indirect::HLD => {
+            Indirect::Cff => self.write_bus(bus, 0xFF00 | u16::from(self.registers.c), val),
+            Indirect::Hld => {
                 let addr = self.registers.hl();
                 self.registers.write_hl(addr.wrapping_sub(1));
                 self.write_bus(bus, addr, val)
             }
-            Indirect::HLI => {
+            Indirect::Hli => {
                 let addr = self.registers.hl();
                 self.registers.write_hl(addr.wrapping_add(1));
                 self.write_bus(bus, addr, val)
@@ -188,7 +189,7 @@ impl IO8<Indirect> for Cpu {
 impl IO8<Direct8> for Cpu {
     fn read8(&mut self, bus: &mut Bus, src: Direct8) -> u8 {
         let lo = self.read8(bus, Imm8);
-        let hi = if let Direct8::DFF = src {
+        let hi = if let Direct8::Dff = src {
             0xFF
         } else {
             self.read8(bus, Imm8)
@@ -198,7 +199,7 @@ impl IO8<Direct8> for Cpu {
 
     fn write8(&mut self, bus: &mut Bus, dst: Direct8, val: u8) {
         let lo = self.read8(bus, Imm8);
-        let hi = if let Direct8::DFF = dst {
+        let hi = if let Direct8::Dff = dst {
             0xFF
         } else {
             self.read8(bus, Imm8)
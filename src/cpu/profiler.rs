@@ -0,0 +1,376 @@
+use std::{cmp::Reverse, collections::HashMap, fmt};
+
+// routine entry point, identified by its address and ROM bank (None outside ROM)
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Routine {
+    pub bank: Option<usize>,
+    pub addr: u16,
+}
+
+impl Routine {
+    pub fn new(bank: Option<usize>, addr: u16) -> Self {
+        Self { bank, addr }
+    }
+}
+
+impl fmt::Display for Routine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "${:02X}:{:04X}", bank, self.addr),
+            None => write!(f, "${:04X}", self.addr),
+        }
+    }
+}
+
+// node of the call tree, node 0 is the root (code running outside of any call)
+struct Node {
+    routine: Option<Routine>,
+    children: HashMap<Routine, usize>,
+    calls: u64,
+    self_cycles: u64,
+}
+
+// entry of the shadow call stack
+struct Frame {
+    node: usize,
+    sp: u16, // sp pointing at the return address
+}
+
+pub struct Profiler {
+    nodes: Vec<Node>,
+    stack: Vec<Frame>,
+    banks: HashMap<Option<usize>, u64>,
+    sample_interval: u64,
+    countdown: u64,
+}
+
+impl Profiler {
+    // sample_interval == 1 accounts every M-cycle (instrumenting),
+    // larger values attribute a whole interval to the routine running at the sample point
+    pub fn new(sample_interval: u64) -> Self {
+        assert!(sample_interval > 0, "invalid sample interval");
+        Self {
+            nodes: vec![Node {
+                routine: None,
+                children: HashMap::new(),
+                calls: 0,
+                self_cycles: 0,
+            }],
+            stack: vec![],
+            banks: HashMap::new(),
+            sample_interval,
+            countdown: sample_interval,
+        }
+    }
+
+    fn current(&self) -> usize {
+        self.stack.last().map_or(0, |frame| frame.node)
+    }
+
+    // call / rst / interrupt dispatch, sp is the value after pushing the return address
+    pub fn call(&mut self, routine: Routine, sp: u16) {
+        let parent = self.current();
+        let node = match self.nodes[parent].children.get(&routine) {
+            Some(&node) => node,
+            None => {
+                self.nodes.push(Node {
+                    routine: Some(routine),
+                    children: HashMap::new(),
+                    calls: 0,
+                    self_cycles: 0,
+                });
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.insert(routine, node);
+                node
+            }
+        };
+        self.nodes[node].calls += 1;
+        self.stack.push(Frame { node, sp });
+    }
+
+    // ret / reti, sp is the value before popping the return address.
+    // frames whose return address is at or below sp are discarded, so routines which drop
+    // their own return address (e.g. `pop hl; ret`) unwind correctly.
+    // a ret above the top frame (e.g. `push de; ret` as jump) is ignored.
+    pub fn ret(&mut self, sp: u16) {
+        while self.stack.last().is_some_and(|frame| frame.sp <= sp) {
+            self.stack.pop();
+        }
+    }
+
+    // bank: ROM bank of the current pc
+    pub fn tick(&mut self, bank: Option<usize>) {
+        self.countdown -= 1;
+        if self.countdown == 0 {
+            self.countdown = self.sample_interval;
+            let node = self.current();
+            self.nodes[node].self_cycles += self.sample_interval;
+            *self.banks.entry(bank).or_default() += self.sample_interval;
+        }
+    }
+
+    fn total_cycles(&self, node: usize) -> u64 {
+        self.nodes[node].self_cycles
+            + self.nodes[node]
+                .children
+                .values()
+                .map(|&child| self.total_cycles(child))
+                .sum::<u64>()
+    }
+
+    fn sorted_children(&self, node: usize) -> Vec<(usize, u64)> {
+        let mut children: Vec<(usize, u64)> = self.nodes[node]
+            .children
+            .values()
+            .map(|&child| (child, self.total_cycles(child)))
+            .collect();
+        children.sort_by_key(|&(_, cycles)| Reverse(cycles));
+        children
+    }
+
+    fn name(&self, node: usize) -> String {
+        match self.nodes[node].routine {
+            Some(routine) => routine.to_string(),
+            None => "(root)".to_string(),
+        }
+    }
+
+    // per routine: self cycles, total cycles, calls
+    // total cycles of recursive calls are counted only at the outermost frame
+    fn flat(&self) -> Vec<(Routine, u64, u64, u64)> {
+        let mut flat: HashMap<Routine, (u64, u64, u64)> = HashMap::new();
+        let mut todo = vec![(0, vec![])];
+        while let Some((node, ancestors)) = todo.pop() {
+            if let Some(routine) = self.nodes[node].routine {
+                let entry = flat.entry(routine).or_default();
+                entry.0 += self.nodes[node].self_cycles;
+                if !ancestors.contains(&routine) {
+                    entry.1 += self.total_cycles(node);
+                }
+                entry.2 += self.nodes[node].calls;
+            }
+            for &child in self.nodes[node].children.values() {
+                let mut ancestors = ancestors.clone();
+                ancestors.extend(self.nodes[node].routine);
+                todo.push((child, ancestors));
+            }
+        }
+        let mut flat: Vec<(Routine, u64, u64, u64)> = flat
+            .into_iter()
+            .map(|(routine, (self_cycles, total, calls))| (routine, self_cycles, total, calls))
+            .collect();
+        flat.sort_by_key(|&(_, self_cycles, _, _)| Reverse(self_cycles));
+        flat
+    }
+
+    fn banks(&self) -> Vec<(Option<usize>, u64)> {
+        let mut banks: Vec<(Option<usize>, u64)> = self
+            .banks
+            .iter()
+            .map(|(&bank, &cycles)| (bank, cycles))
+            .collect();
+        banks.sort_by_key(|&(_, cycles)| Reverse(cycles));
+        banks
+    }
+
+    fn hierarchy(&self, node: usize, depth: usize, total: u64, ret: &mut String) {
+        for (child, cycles) in self.sorted_children(node) {
+            ret.push_str(&format!(
+                "{:>12} {:>6.2}% {:>8} {}{}\n",
+                cycles,
+                percent(cycles, total),
+                self.nodes[child].calls,
+                "  ".repeat(depth),
+                self.name(child)
+            ));
+            self.hierarchy(child, depth + 1, total, ret);
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let total = self.total_cycles(0);
+        let mut ret = format!("total: {} M-cycles\n\nflat profile:\n", total);
+        ret.push_str("        self   self%        total   total%    calls routine\n");
+        ret.push_str(&format!(
+            "{:>12} {:>6.2}% {:>12} {:>7.2}% {:>8} (root)\n",
+            self.nodes[0].self_cycles,
+            percent(self.nodes[0].self_cycles, total),
+            total,
+            100.0,
+            0
+        ));
+        for (routine, self_cycles, cycles, calls) in self.flat() {
+            ret.push_str(&format!(
+                "{:>12} {:>6.2}% {:>12} {:>7.2}% {:>8} {}\n",
+                self_cycles,
+                percent(self_cycles, total),
+                cycles,
+                percent(cycles, total),
+                calls,
+                routine
+            ));
+        }
+
+        ret.push_str("\nper bank:\n");
+        for (bank, cycles) in self.banks() {
+            let bank = match bank {
+                Some(bank) => format!("${:02X}", bank),
+                None => "non-ROM".to_string(),
+            };
+            ret.push_str(&format!(
+                "{:>12} {:>6.2}% {}\n",
+                cycles,
+                percent(cycles, total),
+                bank
+            ));
+        }
+
+        ret.push_str("\ncall tree:\n       total  total%    calls routine\n");
+        ret.push_str(&format!("{:>12} {:>6.2}% {:>8} (root)\n", total, 100.0, 0));
+        self.hierarchy(0, 1, total, &mut ret);
+        ret
+    }
+
+    // folded stacks (`root;caller;callee cycles`) for flamegraph tools
+    pub fn folded(&self) -> String {
+        let mut ret = String::new();
+        let mut todo = vec![(0, self.name(0))];
+        while let Some((node, path)) = todo.pop() {
+            if self.nodes[node].self_cycles > 0 {
+                ret.push_str(&format!("{} {}\n", path, self.nodes[node].self_cycles));
+            }
+            for &child in self.nodes[node].children.values() {
+                todo.push((child, format!("{};{}", path, self.name(child))));
+            }
+        }
+        ret
+    }
+}
+
+fn percent(cycles: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        cycles as f64 * 100.0 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: Routine = Routine {
+        bank: Some(0),
+        addr: 0x0150,
+    };
+    const SUB: Routine = Routine {
+        bank: Some(1),
+        addr: 0x4000,
+    };
+    const VBLANK: Routine = Routine {
+        bank: Some(0),
+        addr: 0x0040,
+    };
+
+    fn ticks(profiler: &mut Profiler, bank: Option<usize>, cycles: u64) {
+        for _ in 0..cycles {
+            profiler.tick(bank);
+        }
+    }
+
+    // root 3, MAIN 4, MAIN > SUB 5, MAIN > VBLANK 4 (interrupt returning with reti)
+    fn profile() -> Profiler {
+        let mut profiler = Profiler::new(1);
+        ticks(&mut profiler, Some(0), 2);
+        profiler.call(MAIN, 0xFFFC);
+        ticks(&mut profiler, Some(0), 3);
+        profiler.call(SUB, 0xFFFA);
+        ticks(&mut profiler, Some(1), 5);
+        profiler.ret(0xFFFA);
+        profiler.call(VBLANK, 0xFFFA);
+        ticks(&mut profiler, Some(0), 4);
+        profiler.ret(0xFFFA);
+        ticks(&mut profiler, Some(0), 1);
+        profiler.ret(0xFFFC);
+        ticks(&mut profiler, None, 1);
+        profiler
+    }
+
+    #[test]
+    fn report() {
+        let report = profile().report();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "total: 16 M-cycles");
+        // flat profile, sorted by self cycles
+        assert_eq!(
+            lines[4],
+            "           3  18.75%           16  100.00%        0 (root)"
+        );
+        assert_eq!(
+            lines[5],
+            "           5  31.25%            5   31.25%        1 $01:4000"
+        );
+        let main = "           4  25.00%           13   81.25%        1 $00:0150";
+        let vblank = "           4  25.00%            4   25.00%        1 $00:0040";
+        assert!(lines[6..8].contains(&main));
+        assert!(lines[6..8].contains(&vblank));
+        // per bank
+        assert_eq!(
+            &lines[9..13],
+            [
+                "per bank:",
+                "          10  62.50% $00",
+                "           5  31.25% $01",
+                "           1   6.25% non-ROM",
+            ]
+        );
+        // call tree, children sorted by total cycles
+        assert_eq!(
+            &lines[16..],
+            [
+                "          16 100.00%        0 (root)",
+                "          13  81.25%        1   $00:0150",
+                "           5  31.25%        1     $01:4000",
+                "           4  25.00%        1     $00:0040",
+            ]
+        );
+    }
+
+    #[test]
+    fn folded() {
+        let folded = profile().folded();
+        let mut lines: Vec<&str> = folded.lines().collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "(root) 3",
+                "(root);$00:0150 4",
+                "(root);$00:0150;$00:0040 4",
+                "(root);$00:0150;$01:4000 5",
+            ]
+        );
+    }
+
+    #[test]
+    fn unwind() {
+        let mut profiler = Profiler::new(1);
+        profiler.call(MAIN, 0xFFFC);
+        profiler.call(SUB, 0xFFFA);
+        // SUB drops its return address (`pop hl; ret`) and returns to the caller of MAIN
+        profiler.ret(0xFFFC);
+        ticks(&mut profiler, Some(0), 1);
+        // `push de; ret` used as a jump is ignored
+        profiler.ret(0xFFFA);
+        ticks(&mut profiler, Some(0), 1);
+        assert_eq!(profiler.folded(), "(root) 2\n");
+    }
+
+    #[test]
+    fn sampling() {
+        let mut profiler = Profiler::new(4);
+        profiler.call(MAIN, 0xFFFC);
+        ticks(&mut profiler, Some(0), 9);
+        assert_eq!(profiler.folded(), "(root);$00:0150 8\n");
+    }
+}
@@ -1,20 +1,64 @@
-use sdl2::{self, event::Event, keyboard::Keycode, Sdl};
+use sdl2::{
+    self,
+    event::{Event, WindowEvent},
+    keyboard::{Keycode, Mod},
+    Sdl,
+};
 
-use std::time;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time,
+};
 
 use crate::{
-    audio::Audio, bootrom::BootRom, bus::Bus, cartridge::Cartridge, cpu::Cpu, joypad::Buttons,
-    lcd::LCD,
+    apu::SAMPLE_RATE,
+    audio::Audio,
+    bootrom::BootRom,
+    bus::Bus,
+    cartridge::Cartridge,
+    cpu::{profiler::Profiler, Cpu, IllegalOpcodePolicy},
+    joypad::Buttons,
+    lcd::Lcd,
+    ppu::{
+        compat_palette::CompatPalette, dmg_palette::DmgPalette, Layers, ViewPalette, LCD_HEIGHT,
+        LCD_WIDTH,
+    },
+    recorder::{Recorder, FRAME_CLOCKS},
+    screenshot::{self, Screenshots},
+    sgb::{SGB_HEIGHT, SGB_WIDTH},
+    viewer::{tiles, Viewers},
+    wav::{SampleFormat, WavRecorder},
 };
 
 pub const CPU_CLOCK_HZ: u128 = 4_194_304;
 pub const M_CYCLE_CLOCK: u128 = 4;
-const M_CYCLE_NANOS: u128 = M_CYCLE_CLOCK * 1_000_000_000 / CPU_CLOCK_HZ;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Sgb,
+    Cgb,
+}
 
 pub struct GameBoy {
     cpu: Cpu,
     bus: Bus,
-    sdl: Sdl,
+    sdl: Option<Sdl>, // None: headless
+    profile_path: Option<String>,
+    cdl_path: Option<String>,
+    tiles_path: Option<String>,
+    paused: bool,
+    step_requested: bool, // one instruction while paused
+    viewers: Viewers,
+    viewer_frame: u64, // last frame shown in the debug views
+    screenshots: Screenshots,
+    screenshot_requested: bool, // taken when the current frame is complete
+    screenshot_on_exit: bool,
+    recorder: Option<Recorder>,
+    wav: Option<WavRecorder>,
+    wav_format: SampleFormat,
+    wav_stems: bool, // also record each channel to its own file
 }
 
 fn key_to_joy(keycode: Keycode) -> Option<Buttons> {
@@ -37,33 +81,343 @@ fn key_to_joy(keycode: Keycode) -> Option<Buttons> {
     }
 }
 
+fn on_off(val: bool) -> &'static str {
+    if val {
+        "on"
+    } else {
+        "off"
+    }
+}
+
 impl GameBoy {
-    pub fn new(bootrom: BootRom, cartridge: Cartridge) -> Self {
+    pub fn new(bootrom: BootRom, cartridge: Cartridge, model: Model) -> Self {
         let sdl = sdl2::init().expect("failed to initialize SDL");
-        let lcd = LCD::new(&sdl, 4);
-        let audio = Audio::new(&sdl);
+        Self::with_sdl(bootrom, cartridge, model, Some(sdl))
+    }
+
+    // no window and no audio output, driven by run_frames
+    pub fn headless(bootrom: BootRom, cartridge: Cartridge, model: Model) -> Self {
+        Self::with_sdl(bootrom, cartridge, model, None)
+    }
+
+    fn with_sdl(bootrom: BootRom, cartridge: Cartridge, model: Model, sdl: Option<Sdl>) -> Self {
+        let (window_scale, width, height) = if model == Model::Sgb {
+            (3, SGB_WIDTH, SGB_HEIGHT)
+        } else {
+            (4, LCD_WIDTH, LCD_HEIGHT)
+        };
+        let lcd = sdl
+            .as_ref()
+            .map(|sdl| Lcd::new(sdl, window_scale, width, height));
+        let audio = sdl.as_ref().map(Audio::new);
+        let mut cpu = Cpu::new();
+        let mut bus = Bus::new(bootrom, cartridge, lcd, audio, model == Model::Cgb);
+        if model == Model::Sgb {
+            bus.enable_sgb();
+        }
+        if !bus.bootrom.is_active() {
+            cpu.skip_bootrom(model);
+            bus.skip_bootrom();
+        }
         Self {
-            cpu: Cpu::new(),
-            bus: Bus::new(bootrom, cartridge, lcd, audio),
+            cpu,
+            bus,
             sdl,
+            profile_path: None,
+            cdl_path: None,
+            tiles_path: None,
+            paused: false,
+            step_requested: false,
+            viewers: Viewers::default(),
+            viewer_frame: 0,
+            screenshots: Screenshots::new(PathBuf::from("screenshots")),
+            screenshot_requested: false,
+            screenshot_on_exit: false,
+            recorder: None,
+            wav: None,
+            wav_format: SampleFormat::Int16,
+            wav_stems: false,
+        }
+    }
+
+    // profile report is printed and folded stacks are written to path on exit
+    pub fn enable_profiler(&mut self, path: String, sample_interval: u64) {
+        self.cpu.profiler = Some(Profiler::new(sample_interval));
+        self.profile_path = Some(path);
+    }
+
+    // code/data log is loaded from path if it exists, and saved to path on exit
+    pub fn enable_cdl(&mut self, path: String) {
+        self.bus.enable_cdl();
+        if let Some(cdl) = &mut self.bus.cdl {
+            if Path::new(&path).exists() {
+                cdl.load(Path::new(&path)).expect("failed to load CDL");
+            }
+        }
+        self.cdl_path = Some(path);
+    }
+
+    // VRAM tile data is written to path as PNG on exit
+    pub fn dump_tiles_on_exit(&mut self, path: String) {
+        self.tiles_path = Some(path);
+    }
+
+    // DMG games on CGB: palette selected instead of the one chosen by the boot ROM
+    pub fn set_compat_palette(&mut self, palette: CompatPalette) {
+        self.bus.ppu.set_compat_palette(palette);
+    }
+
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.bus.ppu.dmg_palette = palette;
+    }
+
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.bus.ppu.color_correction = enabled;
+    }
+
+    // debug: hide layers or individual sprites regardless of LCDC
+    pub fn set_layers(&mut self, layers: Layers) {
+        self.bus.ppu.layers = layers;
+    }
+
+    pub fn layers(&self) -> Layers {
+        self.bus.ppu.layers
+    }
+
+    pub fn set_screenshot_dir(&mut self, dir: PathBuf) {
+        self.screenshots.dir = dir;
+    }
+
+    // window: scaled as the window currently is instead of the native resolution
+    pub fn set_screenshot_scale(&mut self, window: bool) {
+        self.screenshots.window_scale = window;
+    }
+
+    // the last frame, also for headless runs
+    pub fn screenshot_on_exit(&mut self) {
+        self.screenshot_on_exit = true;
+    }
+
+    // save the current frame buffer as a timestamped PNG in the screenshot directory
+    pub fn screenshot(&self) -> io::Result<PathBuf> {
+        self.screenshots.save(&self.bus.ppu)
+    }
+
+    // lossless video and audio, written on a separate thread until stop_recording
+    pub fn start_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.stop_recording()?;
+        let (width, height) = self.bus.ppu.frame_size();
+        self.recorder = Some(Recorder::start(path, width, height)?);
+        self.update_audio_recording();
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        self.flush_audio();
+        let ret = match self.recorder.take() {
+            Some(recorder) => recorder.stop(),
+            None => Ok(()),
+        };
+        self.update_audio_recording();
+        ret
+    }
+
+    fn toggle_recording(&mut self) {
+        if self.recorder.is_some() {
+            match self.stop_recording() {
+                Ok(()) => println!("recording stopped"),
+                Err(e) => eprintln!("failed to write the recording: {}", e),
+            }
+            return;
         }
+        let path = self.capture_path("recording", "avi");
+        match path.and_then(|path| self.start_recording(&path).map(|_| path)) {
+            Ok(path) => println!("recording to {}", path.display()),
+            Err(e) => eprintln!("failed to start recording: {}", e),
+        }
+    }
+
+    // channel: 1-4, only the host output is affected
+    pub fn set_channel_muted(&mut self, channel: usize, muted: bool) {
+        self.bus.apu.set_muted(channel, muted);
+    }
+
+    pub fn set_channel_solo(&mut self, channel: usize, solo: bool) {
+        self.bus.apu.set_solo(channel, solo);
+    }
+
+    // sample format and per-channel stems of the following WAV recordings
+    pub fn set_wav_format(&mut self, format: SampleFormat, stems: bool) {
+        self.wav_format = format;
+        self.wav_stems = stems;
+    }
+
+    // the APU output until stop_wav_recording
+    pub fn start_wav_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.stop_wav_recording()?;
+        let wav = WavRecorder::start(path, self.wav_format, self.wav_stems, SAMPLE_RATE as u32)?;
+        self.wav = Some(wav);
+        self.update_audio_recording();
+        Ok(())
+    }
+
+    pub fn stop_wav_recording(&mut self) -> io::Result<()> {
+        self.flush_audio();
+        let ret = match self.wav.take() {
+            Some(wav) => wav.finish(),
+            None => Ok(()),
+        };
+        self.update_audio_recording();
+        ret
+    }
+
+    fn toggle_wav_recording(&mut self) {
+        if self.wav.is_some() {
+            match self.stop_wav_recording() {
+                Ok(()) => println!("audio recording stopped"),
+                Err(e) => eprintln!("failed to write the audio recording: {}", e),
+            }
+            return;
+        }
+        let path = self.capture_path("audio", "wav");
+        match path.and_then(|path| self.start_wav_recording(&path).map(|_| path)) {
+            Ok(path) => println!("recording audio to {}", path.display()),
+            Err(e) => eprintln!("failed to start audio recording: {}", e),
+        }
+    }
+
+    // timestamped file in the screenshot directory
+    fn capture_path(&self, name: &str, ext: &str) -> io::Result<PathBuf> {
+        let dir = &self.screenshots.dir;
+        fs::create_dir_all(dir)?;
+        Ok(dir.join(format!("{}-{}.{}", name, screenshot::timestamp(), ext)))
+    }
+
+    fn update_audio_recording(&mut self) {
+        let stems = self.wav.as_ref().is_some_and(|wav| wav.has_stems());
+        let mix = self.recorder.is_some() || self.wav.is_some();
+        self.bus.apu.set_recording(mix, stems);
+    }
+
+    // APU output since the last call, written to the WAV recording
+    fn take_audio(&mut self) -> Vec<f32> {
+        let mix = self.bus.apu.take_recorded();
+        let stems = self.bus.apu.take_stems();
+        if let Some(wav) = &mut self.wav {
+            if let Err(e) = wav.write(&mix, &stems) {
+                eprintln!("failed to write the audio recording: {}", e);
+                self.wav = None;
+                self.update_audio_recording();
+            }
+        }
+        mix
+    }
+
+    fn flush_audio(&mut self) {
+        let mix = self.take_audio();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.audio(&mix);
+        }
+    }
+
+    fn save_requested_screenshot(&mut self) {
+        if std::mem::take(&mut self.screenshot_requested) {
+            match self.screenshot() {
+                Ok(path) => println!("saved {}", path.display()),
+                Err(e) => eprintln!("failed to save the screenshot: {}", e),
+            }
+        }
+    }
+
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.cpu.illegal_opcode_policy = policy;
     }
 
     pub fn run(&mut self) {
         let time = time::Instant::now();
-        let mut event_pump = self.sdl.event_pump().unwrap();
-        let mut elapsed = 0;
+        let sdl = self.sdl.clone().expect("no SDL in headless mode");
+        let mut event_pump = sdl.event_pump().unwrap();
         'running: loop {
-            let e = time.elapsed().as_nanos();
-            for _ in 0..(e - elapsed) / M_CYCLE_NANOS {
+            // pace by the normal speed clock, CPU cycles are shorter in CGB double speed mode
+            let target = time.elapsed().as_nanos() * CPU_CLOCK_HZ / 1_000_000_000;
+            while self.bus.clocks < target {
                 for event in event_pump.poll_iter() {
                     match event {
                         Event::Quit { .. } => break 'running,
+                        Event::Window {
+                            window_id,
+                            win_event: WindowEvent::Close,
+                            ..
+                        } => {
+                            if self.viewers.close(window_id) {
+                                continue;
+                            }
+                            // main window
+                            break 'running;
+                        }
+                        Event::MouseMotion {
+                            window_id, x, y, ..
+                        } => self.viewers.mouse_motion(window_id, x, y, &self.bus.ppu),
                         Event::KeyDown {
-                            keycode: Some(key), ..
+                            keycode: Some(key),
+                            keymod,
+                            window_id,
+                            ..
                         } => {
-                            if key == Keycode::Escape {
-                                break 'running;
+                            if self.viewers.key_down(window_id, key, &mut self.bus.ppu) {
+                                continue;
+                            }
+                            match key {
+                                Keycode::Escape => break 'running,
+                                // debug views
+                                Keycode::F1 => self.viewers.toggle_tiles(&sdl, &self.bus.ppu),
+                                Keycode::F2 => self.viewers.toggle_tilemap(&sdl, &self.bus.ppu),
+                                Keycode::F3 => self.viewers.toggle_oam(&sdl, &self.bus.ppu),
+                                // audio channels: mute, solo with shift
+                                Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4 => {
+                                    let channel = match key {
+                                        Keycode::Num1 => 1,
+                                        Keycode::Num2 => 2,
+                                        Keycode::Num3 => 3,
+                                        _ => 4,
+                                    };
+                                    let apu = &mut self.bus.apu;
+                                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                                        let solo = !apu.is_solo(channel);
+                                        apu.set_solo(channel, solo);
+                                        println!("channel {} solo: {}", channel, on_off(solo));
+                                    } else {
+                                        let muted = !apu.is_muted(channel);
+                                        apu.set_muted(channel, muted);
+                                        println!("channel {} muted: {}", channel, on_off(muted));
+                                    }
+                                }
+                                Keycode::F9 => self.toggle_wav_recording(),
+                                Keycode::F10 => self.toggle_recording(),
+                                Keycode::F12 => self.screenshot_requested = true,
+                                // layer switches
+                                Keycode::F5 | Keycode::F6 | Keycode::F7 => {
+                                    let mut layers = self.layers();
+                                    let (name, layer) = match key {
+                                        Keycode::F5 => ("background", &mut layers.bg),
+                                        Keycode::F6 => ("window", &mut layers.window),
+                                        _ => ("sprites", &mut layers.sprites),
+                                    };
+                                    *layer = !*layer;
+                                    println!("{}: {}", name, on_off(*layer));
+                                    self.set_layers(layers);
+                                }
+                                // after a break on an illegal opcode: resume, step with shift
+                                Keycode::F8 if self.paused => {
+                                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                                        self.step_requested = true;
+                                    } else {
+                                        self.cpu.skip_illegal_opcode(&mut self.bus);
+                                        self.paused = false;
+                                        println!("emulation resumed");
+                                    }
+                                }
+                                _ => {}
                             }
                             if let Some(button) = key_to_joy(key) {
                                 self.bus.joypad.press(&mut self.cpu.interrupts, button);
@@ -79,10 +433,192 @@ impl GameBoy {
                         _ => {}
                     }
                 }
-                self.cpu.emulate_cycle(&mut self.bus);
+                let clocks = self.bus.clocks;
+                let step = std::mem::take(&mut self.step_requested);
+                // the debug views of a paused system are refreshed after each step
+                let mut refresh = step;
+                if step {
+                    self.cpu.skip_illegal_opcode(&mut self.bus);
+                }
+                if !self.paused || step {
+                    self.cpu.emulate_cycle(&mut self.bus);
+                }
+                if self.report_illegal_opcode() {
+                    eprintln!("emulation paused, F8: skip the opcode and resume, Shift+F8: step");
+                    self.paused = true;
+                    refresh = true;
+                }
+                if self.bus.clocks == clocks {
+                    // paused or the system clock is stopped, let the host time pass
+                    self.bus.clocks = target;
+                }
+                if !self.frame_done() && self.paused {
+                    // no frame is completed while paused on a break
+                    if refresh {
+                        self.viewers.update(&self.bus.ppu);
+                    }
+                    self.save_requested_screenshot();
+                }
+            }
+            self.flush_audio();
+        }
+        self.exit();
+    }
 
-                elapsed += M_CYCLE_NANOS;
+    // headless: runs as fast as possible for the emulated time of the given number of
+    // frames, whether the LCD is on or not
+    pub fn run_frames(&mut self, frames: u64) {
+        let end = self.bus.clocks + frames as u128 * FRAME_CLOCKS as u128;
+        let mut flush = self.bus.clocks;
+        while self.bus.clocks < end {
+            let clocks = self.bus.clocks;
+            self.cpu.emulate_cycle(&mut self.bus);
+            if self.report_illegal_opcode() {
+                eprintln!("emulation stopped");
+                break;
+            }
+            if self.bus.clocks == clocks {
+                // the system clock is stopped
+                self.bus.clocks += M_CYCLE_CLOCK;
             }
+            self.frame_done();
+            if self.bus.clocks >= flush {
+                flush += FRAME_CLOCKS as u128;
+                self.flush_audio();
+            }
+        }
+        self.exit();
+    }
+
+    // true if the CPU hit an illegal opcode and the emulation should break
+    fn report_illegal_opcode(&mut self) -> bool {
+        let Some(illegal) = self.cpu.take_illegal_opcode() else {
+            return false;
+        };
+        let bank = match illegal.bank {
+            Some(bank) => format!("{:02X}:", bank),
+            None => String::new(),
+        };
+        eprintln!(
+            "illegal opcode {:02X} at ${}{:04X}, CPU locked up",
+            illegal.opcode, bank, illegal.addr
+        );
+        self.cpu.illegal_opcode_policy == IllegalOpcodePolicy::Break
+    }
+
+    // after a frame is drawn: debug views, recording and a requested screenshot
+    fn frame_done(&mut self) -> bool {
+        if self.viewer_frame == self.bus.ppu.frames {
+            return false;
+        }
+        self.viewer_frame = self.bus.ppu.frames;
+        self.viewers.update(&self.bus.ppu);
+        let samples = self.take_audio();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.frame(self.bus.ppu.pixel_buffer(), &samples);
+        }
+        self.save_requested_screenshot();
+        true
+    }
+
+    // recordings, profile, code/data log and tile dump are written on exit
+    fn exit(&mut self) {
+        self.screenshot_requested |= self.screenshot_on_exit;
+        self.save_requested_screenshot();
+        if let Err(e) = self.stop_recording() {
+            eprintln!("failed to write the recording: {}", e);
+        }
+        if let Err(e) = self.stop_wav_recording() {
+            eprintln!("failed to write the audio recording: {}", e);
+        }
+        if let (Some(profiler), Some(path)) = (&self.cpu.profiler, &self.profile_path) {
+            print!("{}", profiler.report());
+            fs::write(path, profiler.folded()).expect("failed to write profile");
+        }
+        if let (Some(cdl), Some(path)) = (&self.bus.cdl, &self.cdl_path) {
+            print!("{}", cdl.summary());
+            cdl.save(Path::new(path)).expect("failed to save CDL");
         }
+        if let Some(path) = &self.tiles_path {
+            tiles::save(Path::new(path), &self.bus.ppu, ViewPalette::Gray)
+                .expect("failed to save tiles");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    // video frames and audio sample frames of a headless recording
+    fn record(name: &str, program: &[u8], frames: u64) -> (u32, u32) {
+        let cartridge = Cartridge::with_program(program, false);
+        let mut gameboy = GameBoy::headless(BootRom::none(), cartridge, Model::Dmg);
+        let path = temp_path(name);
+        gameboy.start_recording(&path).unwrap();
+        gameboy.run_frames(frames);
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // stream lengths in the strh headers
+        let strh: Vec<usize> = (0..data.len() - 4)
+            .filter(|&i| data[i..i + 4] == *b"strh")
+            .collect();
+        assert_eq!(strh.len(), 2);
+        (u32_at(&data, strh[0] + 40), u32_at(&data, strh[1] + 40))
+    }
+
+    // the video stays within 1.5 frames of the audio
+    fn assert_in_sync(video: u32, audio: u32) {
+        let video = video as f64 * FRAME_CLOCKS as f64 / CPU_CLOCK_HZ as f64;
+        let audio = audio as f64 / SAMPLE_RATE as f64;
+        let frame = FRAME_CLOCKS as f64 / CPU_CLOCK_HZ as f64;
+        assert!((video - audio).abs() <= frame * 1.5, "{} {}", video, audio);
+    }
+
+    #[test]
+    fn headless_recording() {
+        // jr -2
+        let (video, audio) = record("lcd-on.avi", &[0x18, 0xFE], 60);
+        // the first frame after turning the LCD on is not shown
+        assert_eq!(video, 59);
+        let expected = 60 * FRAME_CLOCKS as u64 * SAMPLE_RATE as u64 / CPU_CLOCK_HZ as u64;
+        assert!(audio.abs_diff(expected as u32) <= 1, "{}", audio);
+        assert_in_sync(video, audio);
+    }
+
+    #[test]
+    fn headless_recording_lcd_off() {
+        // xor a; ldh ($40), a; jr -2
+        let (video, audio) = record("lcd-off.avi", &[0xAF, 0xE0, 0x40, 0x18, 0xFE], 60);
+        // the last frame is repeated to keep up with the audio
+        assert!(video >= 58, "{}", video);
+        assert_in_sync(video, audio);
+    }
+
+    #[test]
+    fn headless_screenshot() {
+        let cartridge = Cartridge::with_program(&[0x18, 0xFE], false);
+        let mut gameboy = GameBoy::headless(BootRom::none(), cartridge, Model::Dmg);
+        let dir = temp_path("screenshots");
+        gameboy.set_screenshot_dir(dir.clone());
+        // no window to scale to
+        gameboy.set_screenshot_scale(true);
+        gameboy.screenshot_on_exit();
+        gameboy.run_frames(3);
+        let files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let data = fs::read(&files[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        // IHDR width and height
+        assert_eq!(data[12..16], *b"IHDR");
+        assert_eq!(data[16..24], [0, 0, 0, 160, 0, 0, 0, 144]);
     }
 }
@@ -0,0 +1,171 @@
+// CGB VRAM DMA (HDMA1-HDMA5)
+// https://gbdev.io/pandocs/CGB_Registers.html#lcd-vram-dma-transfers
+
+const BLOCK_SIZE: u16 = 0x10;
+
+#[derive(Default)]
+pub struct Hdma {
+    src: u16,
+    dst: u16,     // offset in VRAM
+    blocks: u8,   // 16-byte blocks left to transfer
+    active: bool, // HBlank DMA in progress
+    burst: u16,   // bytes left in the current block, the CPU is stalled while nonzero
+}
+
+impl Hdma {
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // HDMA1-HDMA4 are write only
+            0xFF51..=0xFF54 => 0xFF,
+            // 0xFF once finished, bit 7 set with the remaining length after a cancel
+            0xFF55 => (!self.active as u8) << 7 | (self.blocks.wrapping_sub(1) & 0x7F),
+            _ => panic!("Invalid HDMA read: {:04x}", addr),
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF51 => self.src = (self.src & 0x00FF) | (val as u16) << 8,
+            0xFF52 => self.src = (self.src & 0xFF00) | (val & 0xF0) as u16,
+            0xFF53 => self.dst = (self.dst & 0x00FF) | ((val & 0x1F) as u16) << 8,
+            0xFF54 => self.dst = (self.dst & 0xFF00) | (val & 0xF0) as u16,
+            0xFF55 => {
+                if self.active && val & 0x80 == 0 {
+                    // cancel the HBlank DMA
+                    self.active = false;
+                    return;
+                }
+                self.blocks = (val & 0x7F) + 1;
+                if val & 0x80 == 0 {
+                    // general purpose DMA, everything is copied at once
+                    self.burst = self.blocks as u16 * BLOCK_SIZE;
+                } else {
+                    self.active = true;
+                }
+            }
+            _ => panic!("Invalid HDMA write: {:04x}", addr),
+        }
+    }
+
+    // HBlank DMA copies one block each time the PPU enters HBlank
+    pub fn hblank(&mut self) {
+        if self.active && self.burst == 0 {
+            self.burst = BLOCK_SIZE;
+        }
+    }
+
+    pub fn is_transferring(&self) -> bool {
+        self.burst > 0
+    }
+
+    // source and VRAM destination of the next byte
+    pub fn next(&mut self) -> (u16, u16) {
+        let ret = (self.src, 0x8000 | (self.dst & 0x1FFF));
+        self.src = self.src.wrapping_add(1);
+        self.dst = self.dst.wrapping_add(1);
+        self.burst -= 1;
+        if self.dst & 0x0F == 0 {
+            self.blocks -= 1;
+            if self.blocks == 0 {
+                self.active = false;
+                self.burst = 0;
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // source 0x4000, destination 0x8800
+    fn hdma(hdma5: u8) -> Hdma {
+        let mut hdma = Hdma::default();
+        for (addr, val) in [
+            (0xFF51, 0x40),
+            (0xFF52, 0x00),
+            (0xFF53, 0x08),
+            (0xFF54, 0x00),
+        ] {
+            hdma.write(addr, val);
+        }
+        hdma.write(0xFF55, hdma5);
+        hdma
+    }
+
+    fn transfer(hdma: &mut Hdma) -> Vec<(u16, u16)> {
+        let mut ret = vec![];
+        while hdma.is_transferring() {
+            ret.push(hdma.next());
+        }
+        ret
+    }
+
+    #[test]
+    fn general_purpose() {
+        let mut hdma = hdma(0x01);
+        // everything at once, without waiting for HBlank
+        let bytes = transfer(&mut hdma);
+        assert_eq!(bytes.len(), 0x20);
+        assert_eq!(bytes[0], (0x4000, 0x8800));
+        assert_eq!(bytes[0x1F], (0x401F, 0x881F));
+        assert_eq!(hdma.read(0xFF55), 0xFF);
+        hdma.hblank();
+        assert!(!hdma.is_transferring());
+    }
+
+    #[test]
+    fn hblank() {
+        let mut hdma = hdma(0x81);
+        assert!(!hdma.is_transferring());
+        // active, 2 blocks left
+        assert_eq!(hdma.read(0xFF55), 0x01);
+
+        hdma.hblank();
+        let bytes = transfer(&mut hdma);
+        assert_eq!(bytes.len(), 0x10);
+        assert_eq!(bytes[0x0F], (0x400F, 0x880F));
+        assert_eq!(hdma.read(0xFF55), 0x00);
+
+        hdma.hblank();
+        let bytes = transfer(&mut hdma);
+        assert_eq!(
+            bytes,
+            (0..0x10)
+                .map(|i| (0x4010 + i, 0x8810 + i))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(hdma.read(0xFF55), 0xFF);
+        hdma.hblank();
+        assert!(!hdma.is_transferring());
+    }
+
+    #[test]
+    fn one_block_per_hblank() {
+        let mut hdma = hdma(0x83);
+        hdma.hblank();
+        hdma.next();
+        // a second HBlank does not extend the block in progress
+        hdma.hblank();
+        assert_eq!(transfer(&mut hdma).len(), 0x0F);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut hdma = hdma(0x83);
+        hdma.hblank();
+        transfer(&mut hdma);
+        hdma.write(0xFF55, 0x00);
+        // bit 7 set, 3 blocks were left
+        assert_eq!(hdma.read(0xFF55), 0x82);
+        hdma.hblank();
+        assert!(!hdma.is_transferring());
+
+        // restart continues from the current addresses
+        hdma.write(0xFF55, 0x80);
+        hdma.hblank();
+        assert_eq!(transfer(&mut hdma)[0], (0x4010, 0x8810));
+        assert_eq!(hdma.read(0xFF55), 0xFF);
+    }
+}
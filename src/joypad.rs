@@ -37,6 +37,12 @@ pub struct Joypad {
     mode: u8,
     action: u8,
     direction: u8,
+    sgb: bool,                  // receive SGB command packets
+    packet_bits: Option<usize>, // bits received in the current packet, None between packets
+    packets: Vec<u8>,           // packets of the current command
+    command: Option<Vec<u8>>,   // received command
+    players: u8,                // SGB multiplayer (MLT_REQ)
+    player: u8,                 // selected joypad
 }
 
 impl Joypad {
@@ -45,10 +51,37 @@ impl Joypad {
             mode: 0x00,
             action: 0xFF,
             direction: 0xFF,
+            sgb: false,
+            packet_bits: None,
+            packets: vec![],
+            command: None,
+            players: 1,
+            player: 0,
         }
     }
 
+    pub fn enable_sgb(&mut self) {
+        self.sgb = true;
+    }
+
+    pub fn set_players(&mut self, players: u8) {
+        self.players = players;
+        self.player = 0;
+    }
+
+    pub fn take_sgb_command(&mut self) -> Option<Vec<u8>> {
+        self.command.take()
+    }
+
     pub fn read(&self) -> u8 {
+        if self.mode == 0x30 && self.players > 1 {
+            // SGB multiplayer: the selected joypad id with no line selected
+            return 0xFF - self.player;
+        }
+        if self.player != 0 {
+            // the other joypads are not connected
+            return 0xCF | self.mode | 0x0F;
+        }
         let mut ret = 0xCF | self.mode;
         if ret & 0x10 == 0 {
             ret &= self.direction;
@@ -59,8 +92,57 @@ impl Joypad {
         ret
     }
 
+    // any selected input line is low (pressed)
+    pub fn is_input_low(&self) -> bool {
+        self.read() & 0x0F != 0x0F
+    }
+
     pub fn write(&mut self, data: u8) {
+        let prev = self.mode;
         self.mode = data & 0x30;
+        if self.sgb {
+            self.receive_packet_bit(prev);
+            if self.players > 1 && prev & 0x20 == 0 && self.mode & 0x20 != 0 {
+                // P15 going high selects the next joypad
+                self.player = (self.player + 1) & (self.players - 1);
+            }
+        }
+    }
+
+    // SGB packets: a reset pulse (P14 and P15 low), 128 bits (P14 low: 0, P15 low: 1,
+    // LSB first) each followed by both lines high, and a 0 stop bit
+    fn receive_packet_bit(&mut self, prev: u8) {
+        if self.mode == 0x00 {
+            if self.packet_bits.is_none() {
+                self.packets.extend([0; 16]);
+            } else {
+                // restart the current packet
+                let len = self.packets.len();
+                self.packets[len - 16..].fill(0);
+            }
+            self.packet_bits = Some(0);
+            return;
+        }
+        if prev != 0x30 || self.mode == 0x30 {
+            return;
+        }
+        let Some(bits) = self.packet_bits else {
+            return;
+        };
+        if bits == 128 {
+            // stop bit
+            self.packet_bits = None;
+            let length = (self.packets[0] & 0b111).max(1) as usize;
+            if self.packets.len() >= length * 16 {
+                self.command = Some(std::mem::take(&mut self.packets));
+            }
+            return;
+        }
+        if self.mode == 0x10 {
+            let i = self.packets.len() - 16 + bits / 8;
+            self.packets[i] |= 1 << (bits % 8);
+        }
+        self.packet_bits = Some(bits + 1);
     }
 
     pub fn press(&mut self, interrupts: &mut Interrupts, button: Buttons) {
@@ -75,3 +157,78 @@ impl Joypad {
         interrupts.irq(interrupt::JOYPAD);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // reset pulse, 128 bits and the stop bit
+    fn send(joypad: &mut Joypad, packet: &[u8; 16]) {
+        joypad.write(0x00);
+        joypad.write(0x30);
+        for i in 0..128 {
+            let bit = (packet[i / 8] >> (i % 8)) & 1;
+            joypad.write(if bit == 1 { 0x10 } else { 0x20 });
+            joypad.write(0x30);
+        }
+        joypad.write(0x20);
+        joypad.write(0x30);
+    }
+
+    fn packet(first: u8, fill: u8) -> [u8; 16] {
+        let mut packet = [fill; 16];
+        packet[0] = first;
+        packet
+    }
+
+    #[test]
+    fn sgb_packet() {
+        let mut joypad = Joypad::new();
+        joypad.enable_sgb();
+        let data = std::array::from_fn(|i| (i * 17) as u8 | 0x01);
+        send(&mut joypad, &data);
+        assert_eq!(joypad.take_sgb_command(), Some(data.to_vec()));
+        assert_eq!(joypad.take_sgb_command(), None);
+    }
+
+    #[test]
+    fn sgb_multiple_packets() {
+        let mut joypad = Joypad::new();
+        joypad.enable_sgb();
+        // ATTR_BLK in 2 packets
+        send(&mut joypad, &packet(0x04 << 3 | 2, 0xAA));
+        assert_eq!(joypad.take_sgb_command(), None);
+        send(&mut joypad, &packet(0x55, 0x55));
+        let command = joypad.take_sgb_command().unwrap();
+        assert_eq!(command.len(), 32);
+        assert_eq!(command[..16], packet(0x04 << 3 | 2, 0xAA));
+        assert_eq!(command[16..], packet(0x55, 0x55));
+
+        // a length of 0 is 1 packet
+        send(&mut joypad, &packet(0x17 << 3, 0x00));
+        assert_eq!(joypad.take_sgb_command().unwrap().len(), 16);
+    }
+
+    #[test]
+    fn sgb_packet_restart() {
+        let mut joypad = Joypad::new();
+        joypad.enable_sgb();
+        // a reset pulse in the middle of a packet starts it again
+        joypad.write(0x00);
+        joypad.write(0x30);
+        for _ in 0..20 {
+            joypad.write(0x10);
+            joypad.write(0x30);
+        }
+        let data = packet(0x17 << 3 | 1, 0x00);
+        send(&mut joypad, &data);
+        assert_eq!(joypad.take_sgb_command(), Some(data.to_vec()));
+    }
+
+    #[test]
+    fn sgb_disabled() {
+        let mut joypad = Joypad::new();
+        send(&mut joypad, &packet(0x17 << 3 | 1, 0x00));
+        assert_eq!(joypad.take_sgb_command(), None);
+    }
+}
@@ -1,18 +1,19 @@
-use crate::ppu::{LCD_HEIGHT, LCD_WIDTH};
 use sdl2::{pixels::PixelFormatEnum, render::Canvas, video::Window, Sdl};
 
-pub struct LCD(Canvas<Window>);
+pub struct Lcd(Canvas<Window>);
 
-impl LCD {
-    pub fn new(sdl: &Sdl, scale: u32) -> LCD {
+impl Lcd {
+    // width, height: size of the picture, the window is scaled from it
+    pub fn new(sdl: &Sdl, scale: u32, width: usize, height: usize) -> Lcd {
+        Self::with_title(sdl, "gb-emulator", scale, width, height)
+    }
+
+    // also used for the debug views
+    pub fn with_title(sdl: &Sdl, title: &str, scale: u32, width: usize, height: usize) -> Lcd {
         let window = sdl
             .video()
             .expect("failed to initialize SDL video subsystem")
-            .window(
-                "gb-emulator",
-                LCD_WIDTH as u32 * scale,
-                LCD_HEIGHT as u32 * scale,
-            )
+            .window(title, width as u32 * scale, height as u32 * scale)
             .position_centered()
             .resizable()
             .build()
@@ -20,20 +21,30 @@ impl LCD {
         let canvas = window.into_canvas().build().unwrap();
         Self(canvas)
     }
-    pub fn draw(&mut self, pixels: &Box<[u8]>) {
+    // pixels: RGB24
+    pub fn draw(&mut self, pixels: &[u8], width: usize, height: usize) {
         let texture_creator = self.0.texture_creator();
         let mut texture = texture_creator
-            .create_texture_streaming(PixelFormatEnum::RGB24, LCD_WIDTH as u32, LCD_HEIGHT as u32)
+            .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
             .unwrap();
 
-        texture.update(None, pixels, 480).unwrap();
+        texture.update(None, pixels, width * 3).unwrap();
         self.0.clear();
         self.0.copy(&texture, None, None).unwrap();
         self.0.present();
     }
-    pub fn resize(&mut self, width: u32, _: u32) {
+    pub fn window_id(&self) -> u32 {
+        self.0.window().id()
+    }
+
+    pub fn window_size(&self) -> (u32, u32) {
+        self.0.window().size()
+    }
+
+    pub fn set_title(&mut self, title: &str) {
         self.0
-            .set_logical_size(width, width * LCD_HEIGHT as u32 / LCD_WIDTH as u32)
-            .unwrap();
+            .window_mut()
+            .set_title(title)
+            .expect("invalid window title");
     }
 }
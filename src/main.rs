@@ -1,16 +1,36 @@
-use std::{env, fs::File, io::Read};
+use std::{
+    env,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
 
-use gameboy::GameBoy;
+use cpu::IllegalOpcodePolicy;
+use gameboy::{GameBoy, Model};
 
+mod apu;
+mod audio;
+mod avi;
 mod bootrom;
 mod bus;
 mod cartridge;
+mod cdl;
 mod cpu;
 mod gameboy;
+mod hdma;
 mod hram;
+mod joypad;
 mod lcd;
+mod png;
 mod ppu;
+mod recorder;
+mod screenshot;
+mod sgb;
+#[cfg(test)]
+mod test_util;
 mod timer;
+mod viewer;
+mod wav;
 mod wram;
 
 fn file2vec(fname: &str) -> Vec<u8> {
@@ -24,13 +44,241 @@ fn file2vec(fname: &str) -> Vec<u8> {
     }
 }
 
+// comma-separated audio channels, e.g. 1,3
+fn parse_channels(arg: Option<&String>) -> Vec<usize> {
+    arg.and_then(|s| {
+        s.split(',')
+            .map(|c| c.parse().ok().filter(|c| (1..=4).contains(c)))
+            .collect()
+    })
+    .expect("invalid channels, expected a comma-separated list of 1-4")
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("no cartridge\nUsage: {} <cartridge file>", args[0]);
+    let mut cartridge_file = None;
+    let mut profile = None;
+    let mut profile_interval = 1;
+    let mut cdl = None;
+    let mut illegal_opcode_policy = IllegalOpcodePolicy::LockUp;
+    let mut model = None;
+    let mut compat_palette = None;
+    let mut color_correction = false;
+    let mut dmg_palette = None;
+    let mut tiles = None;
+    let mut screenshot_dir = None;
+    let mut screenshot_window_scale = false;
+    let mut screenshot_on_exit = false;
+    let mut record = None;
+    let mut record_wav = None;
+    let mut wav_format = wav::SampleFormat::Int16;
+    let mut wav_stems = false;
+    let mut muted = vec![];
+    let mut solo = vec![];
+    let mut headless = false;
+    let mut frames = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            // write a folded-stack profile to the given file on exit
+            "--profile" => {
+                i += 1;
+                profile = args.get(i).cloned();
+            }
+            // code/data log, merged with the file if it exists and saved on exit
+            "--cdl" => {
+                i += 1;
+                cdl = args.get(i).cloned();
+            }
+            // sample every n M-cycles instead of instrumenting every cycle
+            "--profile-interval" => {
+                i += 1;
+                profile_interval = args
+                    .get(i)
+                    .and_then(|n| n.parse().ok())
+                    .expect("invalid profile interval");
+            }
+            // behaviour on undefined opcodes
+            "--illegal-opcode" => {
+                i += 1;
+                illegal_opcode_policy = match args.get(i).map(String::as_str) {
+                    Some("lockup") => IllegalOpcodePolicy::LockUp,
+                    Some("break") => IllegalOpcodePolicy::Break,
+                    Some("panic") => IllegalOpcodePolicy::Panic,
+                    _ => panic!("invalid illegal opcode policy, expected lockup, break or panic"),
+                };
+            }
+            // hardware to run on, CGB games default to cgb and others to dmg
+            "--model" => {
+                i += 1;
+                model = match args.get(i).map(String::as_str) {
+                    Some("dmg") => Some(Model::Dmg),
+                    Some("sgb") => Some(Model::Sgb),
+                    Some("cgb") => Some(Model::Cgb),
+                    _ => panic!("invalid model, expected dmg, sgb or cgb"),
+                };
+            }
+            // DMG games on CGB: palette of a boot logo button combination, e.g. up-a, left-b
+            "--compat-palette" => {
+                i += 1;
+                compat_palette = Some(
+                    args.get(i)
+                        .and_then(|name| ppu::compat_palette::by_name(name))
+                        .expect("invalid palette, expected <up|left|down|right>[-a|-b]"),
+                );
+            }
+            // DMG colors: gray, dmg, pocket, light, or RGB hex colors for all layers (4)
+            // or for BG, OBJ0 and OBJ1 (12), e.g. e0f8d0,88c070,346856,081820
+            "--palette" => {
+                i += 1;
+                dmg_palette = Some(
+                    args.get(i)
+                        .and_then(|s| ppu::dmg_palette::DmgPalette::parse(s))
+                        .expect("invalid palette, expected a preset or 4 or 12 RGB hex colors"),
+                );
+            }
+            // write VRAM tile data as PNG on exit (F1 shows it while running)
+            "--dump-tiles" => {
+                i += 1;
+                tiles = args.get(i).cloned();
+            }
+            // directory for screenshots (F12), created if missing
+            "--screenshot-dir" => {
+                i += 1;
+                screenshot_dir = args.get(i).map(PathBuf::from);
+            }
+            // screenshots at the native resolution or scaled as the window
+            "--screenshot-scale" => {
+                i += 1;
+                screenshot_window_scale = match args.get(i).map(String::as_str) {
+                    Some("native") => false,
+                    Some("window") => true,
+                    _ => panic!("invalid screenshot scale, expected native or window"),
+                };
+            }
+            // save a screenshot of the last frame on exit
+            "--screenshot-on-exit" => screenshot_on_exit = true,
+            // record video and audio to an AVI file from the start (F10 toggles while running)
+            "--record" => {
+                i += 1;
+                record = args.get(i).map(PathBuf::from);
+            }
+            // record the audio output to a WAV file from the start (F9 toggles while running)
+            "--record-wav" => {
+                i += 1;
+                record_wav = args.get(i).map(PathBuf::from);
+            }
+            // sample format of WAV recordings
+            "--wav-format" => {
+                i += 1;
+                wav_format = match args.get(i).map(String::as_str) {
+                    Some("s16") => wav::SampleFormat::Int16,
+                    Some("f32") => wav::SampleFormat::Float32,
+                    _ => panic!("invalid WAV format, expected s16 or f32"),
+                };
+            }
+            // WAV recordings also write each channel to its own file
+            "--wav-stems" => wav_stems = true,
+            // audio channels (1-4) left out of the host output, e.g. 3,4 (1-4 keys toggle)
+            "--mute" => {
+                i += 1;
+                muted = parse_channels(args.get(i));
+            }
+            // only these audio channels are heard (shift + 1-4 toggles)
+            "--solo" => {
+                i += 1;
+                solo = parse_channels(args.get(i));
+            }
+            // approximate the colors of the CGB LCD
+            "--color-correction" => color_correction = true,
+            // no window and no audio output, runs as fast as possible
+            "--headless" => headless = true,
+            // length of a headless run in frames of emulated time
+            "--frames" => {
+                i += 1;
+                frames = Some(
+                    args.get(i)
+                        .and_then(|n| n.parse().ok())
+                        .expect("invalid number of frames"),
+                );
+            }
+            _ => cartridge_file = Some(args[i].clone()),
+        }
+        i += 1;
+    }
+    let Some(cartridge_file) = cartridge_file else {
+        eprintln!(
+            "no cartridge\nUsage: {} [--profile <folded file>] [--profile-interval <cycles>] [--cdl <file>] [--illegal-opcode <lockup|break|panic>] [--model <dmg|sgb|cgb>] [--compat-palette <palette>] [--color-correction] [--palette <palette>] [--dump-tiles <png file>] [--screenshot-dir <dir>] [--screenshot-scale <native|window>] [--screenshot-on-exit] [--record <avi file>] [--record-wav <wav file>] [--wav-format <s16|f32>] [--wav-stems] [--mute <channels>] [--solo <channels>] [--headless --frames <n>] <cartridge file>",
+            args[0]
+        );
+        return;
     };
-    let cartridge_file = &args[1];
-    let cartridge = cartridge::Cartridge::new(file2vec(cartridge_file).into());
-    let bootrom = bootrom::BootRom::new(file2vec("dmg_bootrom.bin").into());
-    GameBoy::new(bootrom, cartridge).run();
+    let cartridge = cartridge::Cartridge::new(file2vec(&cartridge_file).into());
+    let model = model.unwrap_or(if cartridge.is_cgb() {
+        Model::Cgb
+    } else {
+        Model::Dmg
+    });
+    let bootrom_file = match model {
+        Model::Dmg => "dmg_bootrom.bin",
+        Model::Sgb => "sgb_bootrom.bin",
+        Model::Cgb => "cgb_bootrom.bin",
+    };
+    let bootrom = if model == Model::Dmg || Path::new(bootrom_file).exists() {
+        bootrom::BootRom::new(file2vec(bootrom_file).into())
+    } else {
+        // start from the post-boot state
+        bootrom::BootRom::none()
+    };
+    let mut gameboy = if headless {
+        GameBoy::headless(bootrom, cartridge, model)
+    } else {
+        GameBoy::new(bootrom, cartridge, model)
+    };
+    if let Some(palette) = compat_palette {
+        gameboy.set_compat_palette(palette);
+    }
+    gameboy.set_color_correction(color_correction);
+    if let Some(palette) = dmg_palette {
+        gameboy.set_dmg_palette(palette);
+    }
+    if let Some(dir) = screenshot_dir {
+        gameboy.set_screenshot_dir(dir);
+    }
+    gameboy.set_screenshot_scale(screenshot_window_scale);
+    if screenshot_on_exit {
+        gameboy.screenshot_on_exit();
+    }
+    gameboy.set_illegal_opcode_policy(illegal_opcode_policy);
+    if let Some(profile) = profile {
+        gameboy.enable_profiler(profile, profile_interval);
+    }
+    if let Some(cdl) = cdl {
+        gameboy.enable_cdl(cdl);
+    }
+    if let Some(tiles) = tiles {
+        gameboy.dump_tiles_on_exit(tiles);
+    }
+    if let Some(path) = record {
+        gameboy
+            .start_recording(&path)
+            .expect("failed to start recording");
+    }
+    for channel in muted {
+        gameboy.set_channel_muted(channel, true);
+    }
+    for channel in solo {
+        gameboy.set_channel_solo(channel, true);
+    }
+    gameboy.set_wav_format(wav_format, wav_stems);
+    if let Some(path) = record_wav {
+        gameboy
+            .start_wav_recording(&path)
+            .expect("failed to start audio recording");
+    }
+    if headless {
+        gameboy.run_frames(frames.expect("--headless needs --frames <n>"));
+    } else {
+        gameboy.run();
+    }
 }
@@ -0,0 +1,128 @@
+use std::{fs, io, path::Path};
+
+// minimal PNG encoder for screenshots and debug views: 8-bit RGB, no filtering,
+// stored (uncompressed) deflate blocks
+// https://www.w3.org/TR/png/
+
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xEDB88320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &d in data {
+        a = (a + d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// pixels: RGB24
+pub fn encode(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+    assert_eq!(pixels.len(), width * height * 3, "invalid image size");
+    // each scanline starts with the filter type (0: none)
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for line in pixels.chunks(width * 3) {
+        raw.push(0);
+        raw.extend(line);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(MAX_STORED_BLOCK).collect::<Vec<_>>();
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push((i == blocks.len() - 1) as u8); // BFINAL, BTYPE = 00 (stored)
+        zlib.extend((block.len() as u16).to_le_bytes());
+        zlib.extend((!(block.len() as u16)).to_le_bytes());
+        zlib.extend(*block);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
+
+    let mut ihdr = vec![];
+    ihdr.extend((width as u32).to_be_bytes());
+    ihdr.extend((height as u32).to_be_bytes());
+    ihdr.extend([8, 2, 0, 0, 0]); // 8-bit depth, truecolor, deflate, no filter, no interlace
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"IDAT", &zlib);
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+pub fn save(path: &Path, pixels: &[u8], width: usize, height: usize) -> io::Result<()> {
+    fs::write(path, encode(pixels, width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn tiny_image() {
+        // 2x1: red, blue
+        let png = encode(&[0xFF, 0, 0, 0, 0, 0xFF], 2, 1);
+        assert_eq!(png[..8], *b"\x89PNG\r\n\x1a\n");
+
+        let ihdr = &png[8..33];
+        assert_eq!(ihdr[..8], *b"\0\0\0\x0dIHDR");
+        assert_eq!(ihdr[8..21], [0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        assert_eq!(ihdr[21..], crc32(&ihdr[4..21]).to_be_bytes());
+
+        // scanline: filter type and 6 bytes
+        let raw = [0, 0xFF, 0, 0, 0, 0, 0xFF];
+        let idat = &png[33..33 + 12 + 2 + 5 + 7 + 4];
+        assert_eq!(idat[..8], *b"\0\0\0\x12IDAT");
+        assert_eq!(idat[8..10], [0x78, 0x01]);
+        // final stored block, length and its complement
+        assert_eq!(idat[10..15], [0x01, 0x07, 0x00, 0xF8, 0xFF]);
+        assert_eq!(idat[15..22], raw);
+        assert_eq!(idat[22..26], adler32(&raw).to_be_bytes());
+        assert_eq!(adler32(&raw), 0x0700_01FF);
+        assert_eq!(idat[26..], crc32(&idat[4..26]).to_be_bytes());
+
+        assert_eq!(png[png.len() - 12..], *b"\0\0\0\0IEND\xae\x42\x60\x82");
+        assert_eq!(png.len(), 33 + 30 + 12);
+    }
+
+    #[test]
+    fn stored_blocks() {
+        // 0x10000 bytes of scanlines do not fit in one stored block
+        let (width, height) = (0x5555, 1);
+        let png = encode(&vec![0x80; width * 3 * height], width, height);
+        let zlib = &png[33 + 8..png.len() - 12 - 4];
+        assert_eq!(zlib[2..7], [0x00, 0xFF, 0xFF, 0x00, 0x00]);
+        assert_eq!(
+            zlib[7 + 0xFFFF..7 + 0xFFFF + 5],
+            [0x01, 0x01, 0x00, 0xFE, 0xFF]
+        );
+    }
+}
@@ -1,10 +1,16 @@
-use std::iter;
+use std::collections::VecDeque;
+
+use self::{compat_palette::CompatPalette, dmg_palette::DmgPalette};
 
 use crate::{
     cpu::interrupt::{self, Interrupts},
-    lcd::LCD,
+    lcd::Lcd,
+    sgb::{Sgb, SGB_HEIGHT, SGB_WIDTH},
 };
 
+pub mod compat_palette;
+pub mod dmg_palette;
+
 pub const LCD_WIDTH: usize = 160;
 pub const LCD_HEIGHT: usize = 144;
 pub const LCD_PIXELS: usize = LCD_WIDTH * LCD_HEIGHT;
@@ -21,6 +27,12 @@ const OBP0_ADDR: u16 = 0xFF48;
 const OBP1_ADDR: u16 = 0xFF49;
 const WY_ADDR: u16 = 0xFF4A;
 const WX_ADDR: u16 = 0xFF4B;
+// CGB
+const VBK_ADDR: u16 = 0xFF4F;
+const BCPS_ADDR: u16 = 0xFF68;
+const BCPD_ADDR: u16 = 0xFF69;
+const OCPS_ADDR: u16 = 0xFF6A;
+const OCPD_ADDR: u16 = 0xFF6B;
 // TODO: 以下 bus にも記載しているので DRYじゃない
 const VRAM_ADDR_START: u16 = 0x8000;
 const VRAM_ADDR_END: u16 = 0x9FFF;
@@ -44,11 +56,100 @@ const VBLANK_INTERRUPT: u8 = 1 << 4;
 const HBLANK_INTERRUPT: u8 = 1 << 3;
 const LYC_LY_COINCIDENCE: u8 = 1 << 2;
 
-// sprite flags
-const OBJ2BG_PRIORITY: u8 = 1 << 7;
-const Y_FLIP: u8 = 1 << 6;
-const X_FLIP: u8 = 1 << 5;
-const PALETTE: u8 = 1 << 4;
+// sprite flags / CGB bg tile attributes
+pub const OBJ2BG_PRIORITY: u8 = 1 << 7;
+pub const Y_FLIP: u8 = 1 << 6;
+pub const X_FLIP: u8 = 1 << 5;
+pub const PALETTE: u8 = 1 << 4;
+pub const TILE_VRAM_BANK: u8 = 1 << 3; // CGB
+pub const CGB_PALETTE: u8 = 0b111;
+
+// palette index auto increment (BCPS, OCPS)
+const PALETTE_AUTO_INCREMENT: u8 = 1 << 7;
+
+// debug switches for the layers, independent of LCDC, hidden layers are drawn as color 0
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Layers {
+    pub bg: bool,
+    pub window: bool,
+    pub sprites: bool,
+    pub hidden_sprites: u64, // bitmask of OAM indices
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self {
+            bg: true,
+            window: true,
+            sprites: true,
+            hidden_sprites: 0,
+        }
+    }
+}
+
+impl Layers {
+    pub fn set_sprite_visible(&mut self, oam_idx: usize, visible: bool) {
+        if visible {
+            self.hidden_sprites &= !(1 << oam_idx);
+        } else {
+            self.hidden_sprites |= 1 << oam_idx;
+        }
+    }
+
+    pub fn is_sprite_visible(&self, oam_idx: usize) -> bool {
+        self.sprites && self.hidden_sprites & (1 << oam_idx) == 0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FetcherStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+#[derive(Clone, Copy, Default)]
+struct BgPixel {
+    color: u8,
+    palette: u8,    // CGB
+    priority: bool, // CGB, over sprites
+}
+
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    color: u8,
+    palette: u8,       // OBP0/OBP1 (DMG), OBJ palette 0-7 (CGB)
+    bg_priority: bool, // BG colors 1-3 over this pixel
+    oam_idx: u8,       // CGB priority
+}
+
+// palette used by the debug views to show raw color indices
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ViewPalette {
+    Gray,
+    Bgp,
+    Obp0,
+    Obp1,
+    CgbBg(u8),
+    CgbObj(u8),
+}
+
+impl ViewPalette {
+    // the next palette available on the current hardware
+    pub fn next(self, cgb: bool) -> Self {
+        match self {
+            ViewPalette::Gray => ViewPalette::Bgp,
+            ViewPalette::Bgp => ViewPalette::Obp0,
+            ViewPalette::Obp0 => ViewPalette::Obp1,
+            ViewPalette::Obp1 if cgb => ViewPalette::CgbBg(0),
+            ViewPalette::CgbBg(7) => ViewPalette::CgbObj(0),
+            ViewPalette::CgbBg(n) => ViewPalette::CgbBg(n + 1),
+            ViewPalette::CgbObj(n) if n < 7 => ViewPalette::CgbObj(n + 1),
+            _ => ViewPalette::Gray,
+        }
+    }
+}
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum Mode {
@@ -59,25 +160,72 @@ enum Mode {
 }
 
 pub struct Ppu {
+    cgb: bool, // CGB mode
+    // DMG compatibility mode of the CGB, DMG palettes select colors in palette RAM
+    compat: bool,
+    // overrides the palette chosen by the boot ROM
+    compat_palette: Option<CompatPalette>,
+    pub color_correction: bool,  // CGB LCD color response
+    pub dmg_palette: DmgPalette, // colorization of the DMG shades
+    pub sgb: Option<Sgb>,        // colorization and border of the SGB
+    pub frames: u64,             // frames drawn, for the debug views
+    pub layers: Layers,          // debug layer switches
     mode: Mode,
-    lcdc: u8,                // lcd control
-    stat: u8,                // lcd status
-    scy: u8,                 // scroll y
-    scx: u8,                 // scroll x
-    ly: u8,                  // line y
-    lyc: u8,                 // line y compare
-    bgp: u8,                 // bg palette
-    obp0: u8,                // object palette 0
-    obp1: u8,                // object palette 1
-    wy: u8,                  // window y
-    wx: u8,                  // window x
-    wly: u8,                 // window line y
-    vram: Box<[u8; 0x2000]>, // 8 KiB video ram
-    oam: Box<[u8; 0xA0]>,    // 160 B object attribute memory
-    pub oam_dma: Option<u16>,
-    buffer: Box<[u8; LCD_PIXELS * 4]>,
-    cycles: u16,
-    lcd: LCD, // TODO: 一般化
+    lcdc: u8,                   // lcd control
+    stat: u8,                   // lcd status
+    scy: u8,                    // scroll y
+    scx: u8,                    // scroll x
+    ly: u8,                     // line y
+    lyc: u8,                    // line y compare
+    bgp: u8,                    // bg palette
+    obp0: u8,                   // object palette 0
+    obp1: u8,                   // object palette 1
+    wy: u8,                     // window y
+    wx: u8,                     // window x
+    wly: u8,                    // window line y
+    vram: Box<[u8; 0x4000]>,    // 8 KiB video ram * 2 banks (CGB)
+    vbk: u8,                    // vram bank (CGB)
+    bcps: u8,                   // bg palette index (CGB)
+    ocps: u8,                   // obj palette index (CGB)
+    bg_palette_ram: [u8; 64],   // 8 palettes * 4 colors, RGB555 (CGB)
+    obj_palette_ram: [u8; 64],  // 8 palettes * 4 colors, RGB555 (CGB)
+    oam: Box<[u8; 0xA0]>,       // 160 B object attribute memory
+    dma: u8,                    // oam dma source, upper byte
+    pub oam_dma: Option<u16>,   // source address of the next byte to transfer
+    oam_dma_start: Option<u16>, // transfer to start after the setup cycle
+    oam_dma_delay: u8,          // M-cycles until oam_dma_start starts
+    pub oam_dma_byte: u8,       // last transferred byte, seen by the CPU on the bus
+    // RGB24 (CGB, DMG compatibility mode)
+    buffer: Box<[u8; LCD_PIXELS * 3]>,
+    // DMG: layer (0: BG, 1: OBJ0, 2: OBJ1) << 2 | shade, colorized by dmg_palette
+    indices: Box<[u8; LCD_PIXELS]>,
+    dots: u16,                  // dots in the current line
+    lx: u8,                     // pixels pushed to the LCD in the current line
+    discard: u8,                // pixels to drop before pushing to the LCD
+    stall: u8,                  // dots the pixel output is stalled for
+    bg_fifo: VecDeque<BgPixel>, // background / window pixels
+    obj_fifo: VecDeque<ObjPixel>,
+    fetcher_step: FetcherStep,
+    fetcher_dots: u8,
+    fetcher_x: u8, // tile column
+    fetcher_tile: usize,
+    fetcher_attr: u8, // CGB tile attributes
+    fetcher_low: u8,
+    fetcher_high: u8,
+    window_y_hit: bool,  // WY matched LY in this frame
+    window_active: bool, // fetcher is rendering the window in this line
+    // OAM index and sprite
+    line_sprites: Vec<(u8, Sprite)>,
+    fetched_sprites: u16, // bitmask of line_sprites
+    fetching_sprite: Option<(u8, Sprite)>,
+    // per line: bitmasks of the OAM indices selected and dropped by the 10 sprites limit
+    oam_lines: Box<[(u64, u64); LCD_HEIGHT]>,
+    stat_line: bool,  // STAT interrupt line
+    hblank: bool,     // entered HBlank since the last take_hblank, for HDMA
+    first_line: bool, // first line after turning the LCD on
+    skip_frame: bool, // first frame after turning the LCD on
+    blank: bool,      // a blank screen is shown instead of the frame buffer
+    lcd: Option<Lcd>, // None: headless
 }
 
 #[repr(C)]
@@ -90,9 +238,17 @@ pub struct Sprite {
 }
 
 impl Ppu {
-    pub fn new(lcd: LCD) -> Self {
+    pub fn new(lcd: Option<Lcd>, cgb: bool) -> Self {
         Self {
-            mode: Mode::OAMScan,
+            cgb,
+            compat: false,
+            compat_palette: None,
+            color_correction: false,
+            dmg_palette: DmgPalette::default(),
+            sgb: None,
+            frames: 0,
+            layers: Layers::default(),
+            mode: Mode::HBlank, // LCD is off
             lcdc: 0,
             stat: 0,
             scy: 0,
@@ -105,11 +261,44 @@ impl Ppu {
             wy: 0,
             wx: 0,
             wly: 0,
-            vram: Box::new([0; 0x2000]),
+            vram: Box::new([0; 0x4000]),
+            vbk: 0,
+            bcps: 0,
+            ocps: 0,
+            bg_palette_ram: [0xFF; 64],
+            obj_palette_ram: [0xFF; 64],
             oam: Box::new([0; 0xA0]),
+            dma: 0xFF,
             oam_dma: None,
-            buffer: Box::new([0; LCD_PIXELS * 4]),
-            cycles: 20, // OAM scan mode needs 20 cycles
+            oam_dma_start: None,
+            oam_dma_delay: 0,
+            oam_dma_byte: 0xFF,
+            buffer: Box::new([0; LCD_PIXELS * 3]),
+            indices: Box::new([0; LCD_PIXELS]),
+            dots: 0,
+            lx: 0,
+            discard: 0,
+            stall: 0,
+            bg_fifo: VecDeque::with_capacity(16),
+            obj_fifo: VecDeque::with_capacity(16),
+            fetcher_step: FetcherStep::Tile,
+            fetcher_dots: 0,
+            fetcher_x: 0,
+            fetcher_tile: 0,
+            fetcher_attr: 0,
+            fetcher_low: 0,
+            fetcher_high: 0,
+            window_y_hit: false,
+            window_active: false,
+            line_sprites: Vec::with_capacity(10),
+            fetched_sprites: 0,
+            fetching_sprite: None,
+            oam_lines: Box::new([(0, 0); LCD_HEIGHT]),
+            stat_line: false,
+            hblank: false,
+            first_line: false,
+            skip_frame: false,
+            blank: false,
             lcd,
         }
     }
@@ -122,23 +311,24 @@ impl Ppu {
             SCX_ADDR => self.scx,
             LY_ADDR => self.ly,
             LYC_ADDR => self.lyc,
-            DMA_ADDR => {
-                if self.oam_dma.is_some() {
-                    0xFF
-                } else {
-                    self.oam[addr as usize & 0xFF]
-                }
-            }
+            DMA_ADDR => self.dma,
             BGP_ADDR => self.bgp,
             OBP0_ADDR => self.obp0,
             OBP1_ADDR => self.obp1,
             WY_ADDR => self.wy,
             WX_ADDR => self.wx,
+            VBK_ADDR | BCPS_ADDR | BCPD_ADDR | OCPS_ADDR | OCPD_ADDR if !self.cgb => 0xFF,
+            VBK_ADDR => 0xFE | self.vbk,
+            BCPS_ADDR => 0x40 | self.bcps,
+            OCPS_ADDR => 0x40 | self.ocps,
+            BCPD_ADDR | OCPD_ADDR if self.mode == Mode::Drawing => 0xFF,
+            BCPD_ADDR => self.bg_palette_ram[(self.bcps & 0x3F) as usize],
+            OCPD_ADDR => self.obj_palette_ram[(self.ocps & 0x3F) as usize],
             VRAM_ADDR_START..=VRAM_ADDR_END => {
                 if self.mode == Mode::Drawing {
                     0xFF // can not read vram during drawing
                 } else {
-                    self.vram[addr as usize & 0x1FFF]
+                    self.vram[self.vram_addr(addr)]
                 }
             }
             OAM_ADDR_START..=OAM_ADDR_END => {
@@ -154,24 +344,50 @@ impl Ppu {
 
     pub fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            LCDC_ADDR => self.lcdc = val,
+            LCDC_ADDR => {
+                if self.lcdc & PPU_ENABLE != 0 && val & PPU_ENABLE == 0 {
+                    self.lcd_off();
+                } else if self.lcdc & PPU_ENABLE == 0 && val & PPU_ENABLE != 0 {
+                    self.lcd_on();
+                }
+                self.lcdc = val;
+            }
             STAT_ADDR => self.stat = (self.stat & LYC_LY_COINCIDENCE) | (val & 0xF8), // can not write 0-2 bits
             SCY_ADDR => self.scy = val,
             SCX_ADDR => self.scx = val,
             LY_ADDR => {} // read only
             LYC_ADDR => self.lyc = val,
             DMA_ADDR => {
-                self.oam_dma = Some((val as u16) << 8);
+                // a running transfer continues until the new one starts
+                self.dma = val;
+                self.oam_dma_start = Some((val as u16) << 8);
+                self.oam_dma_delay = 1;
             }
             BGP_ADDR => self.bgp = val,
             OBP0_ADDR => self.obp0 = val,
             OBP1_ADDR => self.obp1 = val,
             WY_ADDR => self.wy = val,
             WX_ADDR => self.wx = val,
+            VBK_ADDR | BCPS_ADDR | BCPD_ADDR | OCPS_ADDR | OCPD_ADDR if !self.cgb => {}
+            VBK_ADDR => self.vbk = val & 1,
+            BCPS_ADDR => self.bcps = val & 0xBF,
+            OCPS_ADDR => self.ocps = val & 0xBF,
+            BCPD_ADDR => {
+                if self.mode != Mode::Drawing {
+                    self.bg_palette_ram[(self.bcps & 0x3F) as usize] = val;
+                }
+                self.bcps = Self::increment_palette_index(self.bcps);
+            }
+            OCPD_ADDR => {
+                if self.mode != Mode::Drawing {
+                    self.obj_palette_ram[(self.ocps & 0x3F) as usize] = val;
+                }
+                self.ocps = Self::increment_palette_index(self.ocps);
+            }
             VRAM_ADDR_START..=VRAM_ADDR_END => {
                 if self.mode != Mode::Drawing {
                     // can not write vram during drawing
-                    self.vram[addr as usize & 0x1FFF] = val;
+                    self.vram[self.vram_addr(addr)] = val;
                 }
             }
             OAM_ADDR_START..=OAM_ADDR_END => {
@@ -186,22 +402,35 @@ impl Ppu {
         }
     }
 
-    // tile data: 16 bytes * 0x180
+    // index into vram for CPU access, through the selected bank
+    fn vram_addr(&self, addr: u16) -> usize {
+        (self.vbk as usize) << 13 | (addr as usize & 0x1FFF)
+    }
+
+    // write ignored during drawing still increments the index
+    fn increment_palette_index(index: u8) -> u8 {
+        if index & PALETTE_AUTO_INCREMENT != 0 {
+            PALETTE_AUTO_INCREMENT | (index.wrapping_add(1) & 0x3F)
+        } else {
+            index
+        }
+    }
+
+    // tile data: 16 bytes * 0x180 (* 2 banks on CGB)
     // tile: 16 bytes
     // pixex: 2 bits
 
-    fn get_pixel_from_tile(&self, tile_idx: usize, row: u8, col: u8) -> u8 {
+    pub fn get_pixel_from_tile(&self, bank: usize, tile_idx: usize, row: u8, col: u8) -> u8 {
         let r = (row << 1) as usize; // 2 bytes per row
         let c = (7 - col) as usize; // col is (7-col) bit
-        let tile_addr = tile_idx << 4;
-        let low = self.vram[(tile_addr | r) & 0x1FFF];
-        let high = self.vram[(tile_addr | (r + 1)) & 0x1FFF];
+        let tile_addr = bank << 13 | ((tile_idx << 4) & 0x1FFF);
+        let low = self.vram[tile_addr | r];
+        let high = self.vram[tile_addr | (r + 1)];
         ((high >> c) & 1) << 1 | ((low >> c) & 1)
     }
 
-    fn get_tile_idx_from_tile_map(&self, tile_map: bool, row: u8, col: u8) -> usize {
-        let start_addr = 0x1800 | ((tile_map as usize) << 10);
-        let ret = self.vram[start_addr | ((row as usize) << 5) + col as usize & 0x3FF];
+    pub fn get_tile_idx_from_tile_map(&self, tile_map: bool, row: u8, col: u8) -> usize {
+        let ret = self.vram[Self::tile_map_addr(tile_map, row, col)];
         if self.lcdc & TILE_DATA_ADDRESSING_MODE == 0 {
             // 0x8800-0x97FF
             (ret as i8 as i16 + 0x100) as usize
@@ -211,238 +440,905 @@ impl Ppu {
         }
     }
 
-    fn render_window(&mut self, bg_prio: &mut [bool; LCD_WIDTH]) {
-        if self.lcdc & WINDOW_ENABLE == 0 || self.lcdc & BG_WINDOW_ENABLE == 0 || self.wy > self.ly
-        {
-            return;
+    fn tile_map_addr(tile_map: bool, row: u8, col: u8) -> usize {
+        let start_addr = 0x1800 | ((tile_map as usize) << 10);
+        start_addr | ((((row as usize) << 5) + col as usize) & 0x3FF)
+    }
+
+    // CGB: attributes of the tile are stored in vram bank 1 at the same address as the index
+    pub fn get_tile_attr_from_tile_map(&self, tile_map: bool, row: u8, col: u8) -> u8 {
+        if self.cgb {
+            self.vram[0x2000 | Self::tile_map_addr(tile_map, row, col)]
+        } else {
+            0
         }
-        let mut wly_add = 0;
-        let y = self.wly;
-        for i in 0..LCD_WIDTH {
-            let (x, overflow) = (i as u8).overflowing_sub(self.wx.wrapping_sub(7));
-            if overflow {
-                continue;
-            }
+    }
 
-            wly_add = 1;
+    // shade of a color index through a DMG palette register
+    fn shade(palette: u8, pixel: u8) -> u8 {
+        (palette >> (pixel << 1)) & 0b11
+    }
 
-            let tile_index =
-                self.get_tile_idx_from_tile_map(self.lcdc & WINDOW_TILE_MAP > 0, y >> 3, x >> 3);
+    // CGB: RGB555 color from palette RAM to RGB24
+    fn cgb_color(&self, palette_ram: &[u8; 64], palette: u8, pixel: u8) -> [u8; 3] {
+        let i = ((palette as usize) << 3) | ((pixel as usize) << 1);
+        let color = u16::from_le_bytes([palette_ram[i], palette_ram[i + 1]]);
+        let [r, g, b] = [0, 5, 10].map(|shift| ((color >> shift) & 0x1F) as u32);
+        if self.color_correction {
+            // the CGB screen mixes the channels and never gets fully saturated
+            [
+                r * 26 + g * 4 + b * 2,
+                g * 24 + b * 8,
+                r * 6 + g * 4 + b * 22,
+            ]
+            .map(|c| (c.min(960) >> 2) as u8)
+        } else {
+            [r, g, b].map(|c| ((c << 3) | (c >> 2)) as u8)
+        }
+    }
 
-            let pixel = self.get_pixel_from_tile(tile_index, y & 7, x & 7);
+    // CGB mode, VRAM bank 1 and color palettes are available
+    pub fn is_cgb(&self) -> bool {
+        self.cgb
+    }
 
-            self.buffer[LCD_WIDTH * self.ly as usize + i] = match (self.bgp >> (pixel << 1)) & 0b11
-            {
-                0b00 => 0xFF,
-                0b01 => 0xAA,
-                0b10 => 0x55,
-                0b11 => 0x00,
-                _ => unreachable!(),
-            };
-            bg_prio[i] = pixel != 0;
-        }
-        self.wly += wly_add;
+    pub fn sprites(&self) -> [Sprite; 40] {
+        unsafe { std::mem::transmute::<[u8; 0xA0], [Sprite; 40]>(*self.oam.as_ref()) }
     }
 
-    fn render_sprite(&mut self, bg_prio: &[bool; LCD_WIDTH]) {
-        if self.lcdc & SPRITE_ENABLE == 0 {
-            return;
+    pub fn sprite_height(&self) -> u8 {
+        if self.lcdc & SPRITE_SIZE == 0 {
+            8
+        } else {
+            16
         }
+    }
 
-        let size = if self.lcdc & SPRITE_SIZE == 0 { 8 } else { 16 };
+    // bitmasks of the OAM indices selected and dropped by the OAM scan of a line
+    pub fn oam_line(&self, ly: usize) -> (u64, u64) {
+        self.oam_lines[ly]
+    }
 
-        let mut sprites: Vec<Sprite> =
-            unsafe { std::mem::transmute::<[u8; 0xA0], [Sprite; 40]>(self.oam.as_ref().clone()) }
-                .into_iter()
-                .filter_map(|mut sprite| {
-                    sprite.y = sprite.y.wrapping_sub(16);
-                    sprite.x = sprite.x.wrapping_sub(8);
-                    if self.ly.wrapping_sub(sprite.y) < size {
-                        Some(sprite)
-                    } else {
-                        None
-                    }
-                })
-                .take(10)
-                .collect();
-        sprites.reverse();
-        sprites.sort_by(|&a, &b| b.x.cmp(&a.x));
-
-        for sprite in sprites {
-            let palette = if sprite.flags & PALETTE == 0 {
-                self.obp0
+    // SCX, SCY
+    pub fn scroll(&self) -> (u8, u8) {
+        (self.scx, self.scy)
+    }
+
+    // WX, WY while the window is enabled
+    pub fn window_position(&self) -> Option<(u8, u8)> {
+        (self.lcdc & WINDOW_ENABLE != 0).then_some((self.wx, self.wy))
+    }
+
+    // tile maps selected for the background and the window (false: 0x9800, true: 0x9C00)
+    pub fn tile_maps(&self) -> (bool, bool) {
+        (
+            self.lcdc & BG_TILE_MAP != 0,
+            self.lcdc & WINDOW_TILE_MAP != 0,
+        )
+    }
+
+    // color of a color index for the debug views
+    pub fn view_color(&self, palette: ViewPalette, pixel: u8) -> [u8; 3] {
+        let dmg = |layer: u8, register: u8| {
+            let shade = Self::shade(register, pixel);
+            if !self.compat {
+                self.dmg_palette.color(layer << 2 | shade)
+            } else if layer == 0 {
+                self.cgb_color(&self.bg_palette_ram, 0, shade)
             } else {
-                self.obp1
-            };
-            let mut tile_idx = sprite.tile_idx as usize;
-            let mut row = if sprite.flags & Y_FLIP == 0 {
-                self.ly.wrapping_sub(sprite.y)
+                self.cgb_color(&self.obj_palette_ram, layer - 1, shade)
+            }
+        };
+        match palette {
+            ViewPalette::Gray => [[0xFF; 3], [0xAA; 3], [0x55; 3], [0x00; 3]][pixel as usize],
+            ViewPalette::Bgp => dmg(0, self.bgp),
+            ViewPalette::Obp0 => dmg(1, self.obp0),
+            ViewPalette::Obp1 => dmg(2, self.obp1),
+            ViewPalette::CgbBg(n) => self.cgb_color(&self.bg_palette_ram, n, pixel),
+            ViewPalette::CgbObj(n) => self.cgb_color(&self.obj_palette_ram, n, pixel),
+        }
+    }
+
+    // KEY0 written by the CGB boot ROM for DMG games, CGB features are locked after boot
+    pub fn enter_compat_mode(&mut self) {
+        self.cgb = false;
+        self.compat = true;
+        if let Some(palette) = self.compat_palette {
+            self.load_compat_palette(palette);
+        }
+    }
+
+    pub fn set_compat_palette(&mut self, palette: CompatPalette) {
+        self.compat_palette = Some(palette);
+        if self.compat {
+            self.load_compat_palette(palette);
+        }
+    }
+
+    // write the colors to BG palette 0 and OBJ palette 0/1 as the boot ROM does
+    pub fn load_compat_palette(&mut self, palette: CompatPalette) {
+        let write = |ram: &mut [u8; 64], i: usize, colors: [u32; 4]| {
+            for (j, rgb) in colors.into_iter().enumerate() {
+                let [_, r, g, b] = rgb.to_be_bytes().map(|c| (c >> 3) as u16);
+                let k = (i << 3) | (j << 1);
+                ram[k..k + 2].copy_from_slice(&(r | g << 5 | b << 10).to_le_bytes());
+            }
+        };
+        write(&mut self.bg_palette_ram, 0, palette.bg);
+        write(&mut self.obj_palette_ram, 0, palette.obj0);
+        write(&mut self.obj_palette_ram, 1, palette.obj1);
+    }
+
+    // OAM scan: the first 10 sprites on the current line in OAM order
+    fn scan_oam(&mut self) {
+        let size = self.sprite_height();
+        let (mut selected, mut dropped) = (0, 0);
+        self.line_sprites.clear();
+        for (i, sprite) in self.sprites().into_iter().enumerate() {
+            if self.ly.wrapping_sub(sprite.y.wrapping_sub(16)) >= size {
+                continue;
+            }
+            if self.line_sprites.len() < 10 {
+                self.line_sprites.push((i as u8, sprite));
+                selected |= 1 << i;
             } else {
-                size - 1 - self.ly.wrapping_sub(sprite.y)
-            };
-            if size == 16 {
-                tile_idx &= 0xFE;
+                dropped |= 1 << i;
             }
-            tile_idx += (row >= 8) as usize;
+        }
+        if let Some(line) = self.oam_lines.get_mut(self.ly as usize) {
+            *line = (selected, dropped);
+        }
+        self.fetched_sprites = 0;
+        self.window_y_hit |= self.ly == self.wy;
+    }
 
-            row &= 7;
+    fn start_drawing(&mut self) {
+        self.scan_oam();
+        self.mode = Mode::Drawing;
+        self.lx = 0;
+        self.discard = self.scx & 7; // fine scroll
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.fetcher_step = FetcherStep::Tile;
+        self.fetcher_dots = 0;
+        self.fetcher_x = 0;
+        self.window_active = false;
+        self.fetching_sprite = None;
+        self.stall = 6; // the first tile fetch is discarded
+    }
 
-            for col in 0..8 {
-                let col_flipped = if sprite.flags & X_FLIP == 0 {
-                    col
-                } else {
-                    7 - col
-                };
-                let pixel = self.get_pixel_from_tile(tile_idx, row, col_flipped);
-                let i = sprite.x.wrapping_add(col) as usize;
-                if i < LCD_WIDTH && pixel != 0 {
-                    if sprite.flags & OBJ2BG_PRIORITY == 0 || !bg_prio[i] {
-                        self.buffer[LCD_WIDTH * self.ly as usize + i] =
-                            match (palette >> (pixel << 1)) & 0b11 {
-                                0b00 => 0xFF,
-                                0b01 => 0xAA,
-                                0b10 => 0x55,
-                                0b11 => 0x00,
-                                _ => unreachable!(),
-                            }
+    // low byte of the fetched tile row, CGB tiles may be y-flipped and in vram bank 1
+    fn fetcher_data_addr(&self, row: u8) -> usize {
+        let row = if self.fetcher_attr & Y_FLIP == 0 {
+            row
+        } else {
+            7 - row
+        };
+        let bank = (self.fetcher_attr & TILE_VRAM_BANK != 0) as usize;
+        bank << 13 | ((self.fetcher_tile << 4) & 0x1FFF) | (row << 1) as usize
+    }
+
+    // background / window pixel fetcher, each step but push takes 2 dots
+    fn fetch_bg(&mut self) {
+        if self.fetcher_step != FetcherStep::Push {
+            self.fetcher_dots += 1;
+            if self.fetcher_dots < 2 {
+                return;
+            }
+            self.fetcher_dots = 0;
+        }
+        let (y, tile_col, tile_map) = if self.window_active {
+            (self.wly, self.fetcher_x, self.lcdc & WINDOW_TILE_MAP != 0)
+        } else {
+            (
+                self.ly.wrapping_add(self.scy),
+                ((self.scx >> 3).wrapping_add(self.fetcher_x)) & 31,
+                self.lcdc & BG_TILE_MAP != 0,
+            )
+        };
+        let row = y & 7;
+        match self.fetcher_step {
+            FetcherStep::Tile => {
+                self.fetcher_tile = self.get_tile_idx_from_tile_map(tile_map, y >> 3, tile_col);
+                self.fetcher_attr = self.get_tile_attr_from_tile_map(tile_map, y >> 3, tile_col);
+                self.fetcher_step = FetcherStep::DataLow;
+            }
+            FetcherStep::DataLow => {
+                self.fetcher_low = self.vram[self.fetcher_data_addr(row)];
+                self.fetcher_step = FetcherStep::DataHigh;
+            }
+            FetcherStep::DataHigh => {
+                self.fetcher_high = self.vram[self.fetcher_data_addr(row) + 1];
+                self.fetcher_step = FetcherStep::Push;
+            }
+            FetcherStep::Push => {
+                if self.bg_fifo.is_empty() {
+                    let visible = if self.window_active {
+                        self.layers.window
+                    } else {
+                        self.layers.bg
+                    };
+                    for c in 0..8 {
+                        let c = if self.fetcher_attr & X_FLIP == 0 {
+                            7 - c
+                        } else {
+                            c
+                        };
+                        let color =
+                            ((self.fetcher_high >> c) & 1) << 1 | ((self.fetcher_low >> c) & 1);
+                        self.bg_fifo.push_back(BgPixel {
+                            color: if visible { color } else { 0 },
+                            palette: self.fetcher_attr & CGB_PALETTE,
+                            priority: self.fetcher_attr & OBJ2BG_PRIORITY != 0,
+                        });
                     }
+                    self.fetcher_x = self.fetcher_x.wrapping_add(1);
+                    self.fetcher_step = FetcherStep::Tile;
                 }
             }
         }
     }
 
-    fn render_bg(&mut self, bg_prio: &mut [bool; LCD_WIDTH]) {
-        if self.lcdc & BG_WINDOW_ENABLE == 0 {
+    // next sprite whose left edge has been reached by the current pixel,
+    // by OAM index on CGB, by x then OAM index on DMG so that the smaller x is merged first
+    fn next_sprite(&mut self) -> Option<(u8, Sprite)> {
+        if self.lcdc & SPRITE_ENABLE == 0 {
+            return None;
+        }
+        let mut candidates = (0..self.line_sprites.len()).filter(|&i| {
+            self.fetched_sprites & (1 << i) == 0
+                && (self.line_sprites[i].1.x as usize) <= self.lx as usize + 8
+        });
+        // line_sprites is in OAM order, min_by_key keeps the first of equal x
+        let i = if self.cgb {
+            candidates.next()
+        } else {
+            candidates.min_by_key(|&i| self.line_sprites[i].1.x)
+        }?;
+        self.fetched_sprites |= 1 << i;
+        Some(self.line_sprites[i])
+    }
+
+    // mix the fetched sprite into the object FIFO, existing opaque pixels have priority
+    // on DMG (smaller x), the smaller OAM index has priority on CGB
+    fn merge_sprite(&mut self, oam_idx: u8, sprite: Sprite) {
+        if !self.layers.is_sprite_visible(oam_idx as usize) {
             return;
         }
-        let y = self.ly.wrapping_add(self.scy);
-        for i in 0..LCD_WIDTH {
-            let x = (i as u8).wrapping_add(self.scx);
-            let tile_idx =
-                self.get_tile_idx_from_tile_map(self.lcdc & BG_TILE_MAP != 0, y >> 3, x >> 3);
-            let pixel = self.get_pixel_from_tile(tile_idx, y & 7, x & 7);
-            self.buffer[LCD_WIDTH * self.ly as usize + i] = match (self.bgp >> (pixel << 1)) & 0b11
-            {
-                0b00 => 0xFF,
-                0b01 => 0xAA,
-                0b10 => 0x55,
-                0b11 => 0x00,
-                _ => unreachable!(),
+        let size = self.sprite_height();
+        let y = sprite.y.wrapping_sub(16);
+        let mut row = if sprite.flags & Y_FLIP == 0 {
+            self.ly.wrapping_sub(y)
+        } else {
+            size - 1 - self.ly.wrapping_sub(y)
+        };
+        let mut tile_idx = sprite.tile_idx as usize;
+        if size == 16 {
+            tile_idx &= 0xFE;
+        }
+        tile_idx += (row >= 8) as usize;
+        row &= 7;
+        let (bank, palette) = if self.cgb {
+            (
+                (sprite.flags & TILE_VRAM_BANK != 0) as usize,
+                sprite.flags & CGB_PALETTE,
+            )
+        } else {
+            (0, (sprite.flags & PALETTE != 0) as u8)
+        };
+
+        while self.obj_fifo.len() < 8 {
+            self.obj_fifo.push_back(ObjPixel::default());
+        }
+        for col in 0..8 {
+            // sprite.x is the screen x + 8
+            let Some(i) = (sprite.x as usize + col).checked_sub(self.lx as usize + 8) else {
+                continue;
+            };
+            let col_flipped = if sprite.flags & X_FLIP == 0 {
+                col as u8
+            } else {
+                7 - col as u8
             };
-            bg_prio[i] = pixel != 0;
+            let color = self.get_pixel_from_tile(bank, tile_idx, row, col_flipped);
+            if i >= 8 || color == 0 {
+                continue;
+            }
+            let current = self.obj_fifo[i];
+            if current.color == 0 || (self.cgb && oam_idx < current.oam_idx) {
+                self.obj_fifo[i] = ObjPixel {
+                    color,
+                    palette,
+                    bg_priority: sprite.flags & OBJ2BG_PRIORITY != 0,
+                    oam_idx,
+                };
+            }
         }
     }
 
-    fn check_lyc_eq_ly(&mut self, interrupts: &mut Interrupts) {
-        if self.ly == self.lyc {
-            self.stat |= LYC_LY_COINCIDENCE;
-            if self.stat & LYC_LY_INTERRUPT != 0 {
-                interrupts.irq(interrupt::LCD_STAT);
+    // mode 3, one dot: fetch and push at most one pixel to the LCD
+    fn emulate_drawing_dot(&mut self) {
+        if self.stall > 0 {
+            self.stall -= 1;
+            if self.stall == 0 {
+                if let Some((oam_idx, sprite)) = self.fetching_sprite.take() {
+                    self.merge_sprite(oam_idx, sprite);
+                }
+            }
+            return;
+        }
+
+        if self.fetching_sprite.is_none() {
+            self.fetching_sprite = self.next_sprite();
+        }
+        if self.fetching_sprite.is_some() {
+            // the sprite fetch starts after the background fetcher has finished its tile
+            if self.fetcher_step == FetcherStep::Push && !self.bg_fifo.is_empty() {
+                self.stall = 5;
+            } else {
+                self.fetch_bg();
             }
+            return;
+        }
+
+        if !self.window_active
+            && self.lcdc & WINDOW_ENABLE != 0
+            && self.window_y_hit
+            && self.lx as u16 + 7 >= self.wx as u16
+        {
+            // switch the fetcher to the window
+            self.window_active = true;
+            self.discard = if self.lx == 0 {
+                7u8.saturating_sub(self.wx)
+            } else {
+                0
+            };
+            self.bg_fifo.clear();
+            self.fetcher_step = FetcherStep::Tile;
+            self.fetcher_dots = 0;
+            self.fetcher_x = 0;
+        }
+
+        self.fetch_bg();
+
+        let Some(bg) = self.bg_fifo.pop_front() else {
+            return;
+        };
+        // discarded BG pixels (SCX fine scroll, window with WX < 7) are not on the LCD,
+        // the object FIFO only shifts with lx
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+        let obj = self
+            .obj_fifo
+            .pop_front()
+            .filter(|obj| obj.color != 0 && self.lcdc & SPRITE_ENABLE != 0);
+
+        let i = LCD_WIDTH * self.ly as usize + self.lx as usize;
+        self.lx += 1;
+        if self.cgb {
+            // LCDC bit 0 is the master priority on CGB, BG is still drawn when cleared
+            let color = match obj {
+                Some(obj)
+                    if self.lcdc & BG_WINDOW_ENABLE == 0
+                        || bg.color == 0
+                        || (!bg.priority && !obj.bg_priority) =>
+                {
+                    self.cgb_color(&self.obj_palette_ram, obj.palette, obj.color)
+                }
+                _ => self.cgb_color(&self.bg_palette_ram, bg.palette, bg.color),
+            };
+            self.buffer[i * 3..i * 3 + 3].copy_from_slice(&color);
+            return;
+        }
+
+        let bg_color = if self.lcdc & BG_WINDOW_ENABLE == 0 {
+            0
         } else {
-            self.stat &= !LYC_LY_COINCIDENCE;
+            bg.color
+        };
+        // layer: 0 BG, 1 OBJ0, 2 OBJ1
+        let (layer, shade) = match obj {
+            Some(obj) if !obj.bg_priority || bg_color == 0 => {
+                let palette = if obj.palette == 0 {
+                    self.obp0
+                } else {
+                    self.obp1
+                };
+                (1 + obj.palette, Self::shade(palette, obj.color))
+            }
+            _ => (0, Self::shade(self.bgp, bg_color)),
+        };
+        if self.compat {
+            // the shade picks a color of BG palette 0 or OBJ palette 0/1 in palette RAM
+            let color = if layer == 0 {
+                self.cgb_color(&self.bg_palette_ram, 0, shade)
+            } else {
+                self.cgb_color(&self.obj_palette_ram, layer - 1, shade)
+            };
+            self.buffer[i * 3..i * 3 + 3].copy_from_slice(&color);
+        } else {
+            self.indices[i] = layer << 2 | shade;
         }
     }
 
-    // drawing (mode: 3) のときは OAM と VRAM にアクセスできないので、
-    // M-cycle ごとの厳密な実装ではなく、 drawing の際にレンダリングすることで実装を簡略化している
-    pub fn emulate_cycle(&mut self, interrupts: &mut Interrupts) -> bool {
-        if self.lcdc & PPU_ENABLE == 0 {
-            return false;
-        }
+    // turning the LCD off resets LY and the mode, and the screen goes blank
+    fn lcd_off(&mut self) {
+        self.ly = 0;
+        self.wly = 0;
+        self.dots = 0;
+        self.mode = Mode::HBlank;
+        self.window_y_hit = false;
+        self.stat_line = false;
+        self.oam_lines.fill((0, 0));
+        self.draw_blank();
+    }
 
-        self.cycles -= 1;
-        if self.cycles > 0 {
-            return false;
+    // the first line after turning the LCD on has no OAM scan (mode 0 instead),
+    // and the first frame is not displayed
+    fn lcd_on(&mut self) {
+        self.first_line = true;
+        self.skip_frame = true;
+    }
+
+    // all STAT interrupt sources share one line, the interrupt is requested only on its
+    // rising edge, so a source going high while another one is high does not fire again
+    fn update_stat_line(&mut self, interrupts: &mut Interrupts) {
+        if self.ly == self.lyc {
+            self.stat |= LYC_LY_COINCIDENCE;
+        } else {
+            self.stat &= !LYC_LY_COINCIDENCE;
         }
+        let line = (self.stat & LYC_LY_INTERRUPT != 0 && self.stat & LYC_LY_COINCIDENCE != 0)
+            || match self.mode {
+                Mode::HBlank => self.stat & HBLANK_INTERRUPT != 0,
+                Mode::VBlank => self.stat & VBLANK_INTERRUPT != 0,
+                Mode::OAMScan => self.stat & OAM_INTERRUPT != 0,
+                Mode::Drawing => false,
+            };
+        if line && !self.stat_line {
+            interrupts.irq(interrupt::LCD_STAT);
+        }
+        self.stat_line = line;
+    }
 
+    // one dot (T-cycle), 456 dots per line
+    fn emulate_dot(&mut self, interrupts: &mut Interrupts) -> bool {
+        self.dots += 1;
         let mut need_vsync = false;
 
         match self.mode {
-            Mode::HBlank => {
-                self.ly += 1;
-                if self.ly < 144 {
-                    self.mode = Mode::OAMScan;
-                    self.cycles = 20;
-                    if self.stat & OAM_INTERRUPT != 0 {
-                        interrupts.irq(interrupt::LCD_STAT);
+            Mode::OAMScan => {
+                if self.dots == 80 {
+                    self.start_drawing();
+                }
+            }
+            Mode::Drawing => {
+                self.emulate_drawing_dot();
+                if self.lx as usize == LCD_WIDTH {
+                    if self.window_active {
+                        self.wly += 1;
                     }
-                } else {
-                    self.mode = Mode::VBlank;
-                    self.cycles = 114;
-                    interrupts.irq(interrupt::VBLANK);
-                    if self.stat & VBLANK_INTERRUPT != 0 {
-                        interrupts.irq(interrupt::LCD_STAT);
+                    self.mode = Mode::HBlank;
+                    self.hblank = true;
+                }
+            }
+            Mode::HBlank => {
+                if self.first_line && self.dots == 80 {
+                    self.first_line = false;
+                    self.start_drawing();
+                } else if self.dots == 456 {
+                    self.dots = 0;
+                    self.ly += 1;
+                    if self.ly < 144 {
+                        self.mode = Mode::OAMScan;
+                    } else {
+                        self.mode = Mode::VBlank;
+                        interrupts.irq(interrupt::VBLANK);
                     }
                 }
-                self.check_lyc_eq_ly(interrupts);
             }
             Mode::VBlank => {
-                self.ly += 1;
-                if self.ly > 153 {
+                if self.ly == 153 && self.dots == 4 {
+                    // LY reads 0 (and is compared with LYC as 0) after the first M-cycle of line 153
                     self.ly = 0;
-                    self.wly = 0;
-                    self.mode = Mode::OAMScan;
-                    self.cycles = 20;
-                    need_vsync = true;
-                    if self.stat & OAM_INTERRUPT != 0 {
-                        interrupts.irq(interrupt::LCD_STAT);
+                } else if self.dots == 456 {
+                    self.dots = 0;
+                    if self.ly == 0 {
+                        // end of line 153
+                        self.wly = 0;
+                        self.window_y_hit = false;
+                        self.mode = Mode::OAMScan;
+                        need_vsync = true;
+                    } else {
+                        self.ly += 1;
                     }
-                } else {
-                    self.cycles = 114;
-                }
-                self.check_lyc_eq_ly(interrupts);
-            }
-            Mode::OAMScan => {
-                self.mode = Mode::Drawing;
-                self.cycles = 43;
-            }
-            Mode::Drawing => {
-                let mut bg_prio = [false; LCD_WIDTH];
-                self.render_bg(&mut bg_prio);
-                self.render_window(&mut bg_prio);
-                self.render_sprite(&bg_prio);
-                self.mode = Mode::HBlank;
-                self.cycles = 51;
-                if self.stat & HBLANK_INTERRUPT != 0 {
-                    interrupts.irq(interrupt::LCD_STAT);
                 }
             }
         }
+        self.update_stat_line(interrupts);
         need_vsync
     }
 
-    pub fn oam_dma_emulate_cycle(&mut self, val: u8) {
-        if let Some(addr) = self.oam_dma {
-            if self.mode != Mode::OAMScan && self.mode != Mode::Drawing {
-                // can not write oam during oam scan or drawing
-                self.oam[addr as usize & 0xFF] = val;
-            }
-            self.oam_dma = Some(addr.wrapping_add(1)).filter(|&x| (x as u8) < 0xA0);
+    // pixel FIFO renderer, so SCX/BGP/LCDC writes during drawing take effect mid-line
+    // and the length of mode 3 depends on the fine scroll, the window and sprite fetches
+    // dots: 4 per CPU M-cycle, 2 in CGB double speed mode
+    pub fn emulate_cycle(&mut self, interrupts: &mut Interrupts, dots: u8) -> bool {
+        if self.lcdc & PPU_ENABLE == 0 {
+            return false;
         }
+
+        let mut need_vsync = false;
+        for _ in 0..dots {
+            need_vsync |= self.emulate_dot(interrupts);
+        }
+        if need_vsync && self.skip_frame {
+            self.skip_frame = false;
+            return false;
+        }
+        need_vsync
+    }
+
+    pub fn take_hblank(&mut self) -> bool {
+        std::mem::take(&mut self.hblank)
     }
 
-    pub fn write_oam(&mut self, addr: u16, val: u8) {
-        if self.mode != Mode::OAMScan && self.mode != Mode::Drawing {
-            // can not write oam during oam scan or drawing
+    // transfer one byte, OAM is written regardless of the PPU mode
+    pub fn oam_dma_emulate_cycle(&mut self, val: u8) {
+        if let Some(addr) = self.oam_dma {
             self.oam[addr as usize & 0xFF] = val;
+            self.oam_dma_byte = val;
+            self.oam_dma = Some(addr.wrapping_add(1)).filter(|&x| (x as u8) < 0xA0);
         }
     }
 
-    pub fn finish_oam_dma(&mut self) {
-        self.oam_dma = None;
-        self.cycles = 160;
+    // a transfer starts after the M-cycle writing to the DMA register and a setup
+    // M-cycle, the CPU keeps the bus during both
+    pub fn oam_dma_start_cycle(&mut self) {
+        if self.oam_dma_delay > 0 {
+            self.oam_dma_delay -= 1;
+        } else if let Some(addr) = self.oam_dma_start.take() {
+            self.oam_dma = Some(addr);
+        }
     }
 
-    // For LCD
+    // For LCD, RGB24. DMG shades are colorized here
     pub fn pixel_buffer(&self) -> Box<[u8]> {
-        self.buffer
+        if self.blank {
+            // white or the lightest DMG color
+            let color = if self.cgb || self.compat {
+                [0xFF; 3]
+            } else {
+                self.dmg_palette.color(0)
+            };
+            let (width, height) = self.frame_size();
+            return color.repeat(width * height).into();
+        }
+        if let Some(sgb) = &self.sgb {
+            return sgb.render();
+        }
+        if self.cgb || self.compat {
+            return Box::from(self.buffer.as_slice());
+        }
+        self.indices
             .iter()
-            .flat_map(|&e| iter::repeat(e.into()).take(3))
-            .collect::<Box<[u8]>>()
+            .flat_map(|&index| self.dmg_palette.color(index))
+            .collect()
+    }
+
+    // size of the picture from pixel_buffer, the SGB adds the border
+    pub fn frame_size(&self) -> (usize, usize) {
+        if self.sgb.is_some() {
+            (SGB_WIDTH, SGB_HEIGHT)
+        } else {
+            (LCD_WIDTH, LCD_HEIGHT)
+        }
+    }
+
+    // whole times the picture fits in the LCD window as resized, 1 when headless
+    pub fn window_scale(&self) -> usize {
+        let (width, height) = self.frame_size();
+        self.lcd.as_ref().map_or(1, |lcd| {
+            let (w, h) = lcd.window_size();
+            (w as usize / width).min(h as usize / height).max(1)
+        })
     }
 
     pub fn draw(&mut self) {
-        self.lcd.draw(&self.pixel_buffer());
+        self.frames += 1;
+        self.blank = false;
+        if let Some(sgb) = &mut self.sgb {
+            sgb.frame(self.indices.as_slice());
+        }
+        let (width, height) = self.frame_size();
+        let pixels = self.pixel_buffer();
+        if let Some(lcd) = &mut self.lcd {
+            lcd.draw(&pixels, width, height);
+        }
+    }
+
+    // present a blank screen without touching the frame buffer (LCD off, STOP mode),
+    // until the next frame is drawn
+    pub fn draw_blank(&mut self) {
+        self.blank = true;
+        let (width, height) = self.frame_size();
+        let pixels = self.pixel_buffer();
+        if let Some(lcd) = &mut self.lcd {
+            lcd.draw(&pixels, width, height);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // tile of a single color
+    fn fill_tile(ppu: &mut Ppu, tile_idx: u16, color: u8) {
+        for row in 0..8 {
+            let addr = 0x8000 + tile_idx * 16 + row * 2;
+            ppu.write(addr, if color & 1 != 0 { 0xFF } else { 0x00 });
+            ppu.write(addr + 1, if color & 2 != 0 { 0xFF } else { 0x00 });
+        }
+    }
+
+    fn set_sprite(ppu: &mut Ppu, oam_idx: usize, y: u8, x: u8, tile_idx: u8) {
+        ppu.oam[oam_idx * 4..oam_idx * 4 + 4].copy_from_slice(&[y, x, tile_idx, 0]);
+    }
+
+    // BG tile 0 blank, sprites on line 0-7, LCD turned on and run for 2 frames
+    fn run(lcdc: u8, setup: impl FnOnce(&mut Ppu)) -> Ppu {
+        let mut ppu = Ppu::new(None, false);
+        ppu.write(BGP_ADDR, 0xE4);
+        ppu.write(OBP0_ADDR, 0xE4);
+        setup(&mut ppu);
+        ppu.write(
+            LCDC_ADDR,
+            lcdc | PPU_ENABLE | SPRITE_ENABLE | BG_WINDOW_ENABLE | TILE_DATA_ADDRESSING_MODE,
+        );
+        let mut interrupts = Interrupts::default();
+        for _ in 0..70224 * 2 / 4 {
+            ppu.emulate_cycle(&mut interrupts, 4);
+        }
+        ppu
+    }
+
+    // (LY, mode) at each STAT interrupt request during the next frame, dot by dot
+    fn stat_requests(ppu: &mut Ppu) -> Vec<(u8, u8)> {
+        let mut interrupts = Interrupts::default();
+        let mut requests = vec![];
+        for _ in 0..70224 {
+            ppu.emulate_cycle(&mut interrupts, 1);
+            if std::mem::take(&mut interrupts.interrupt_flags) & interrupt::LCD_STAT != 0 {
+                requests.push((ppu.read(LY_ADDR), ppu.read(STAT_ADDR) & 3));
+            }
+        }
+        requests
+    }
+
+    // layer << 2 | shade of line 4, x 0 to len - 1
+    fn line(ppu: &Ppu, len: usize) -> Vec<u8> {
+        ppu.indices[LCD_WIDTH * 4..LCD_WIDTH * 4 + len].to_vec()
+    }
+
+    #[test]
+    fn sprite_with_fine_scroll() {
+        for scx in 0..8 {
+            let ppu = run(0, |ppu| {
+                fill_tile(ppu, 1, 3);
+                set_sprite(ppu, 0, 16, 8, 1);
+                ppu.write(SCX_ADDR, scx);
+            });
+            // OBJ0 shade 3 at x 0-7 whatever SCX & 7
+            assert_eq!(
+                line(&ppu, 10),
+                [7, 7, 7, 7, 7, 7, 7, 7, 0, 0],
+                "scx {}",
+                scx
+            );
+        }
+    }
+
+    #[test]
+    fn sprite_at_left_edge_with_window() {
+        for wx in 0..7 {
+            for scx in 0..8 {
+                let ppu = run(WINDOW_ENABLE, |ppu| {
+                    fill_tile(ppu, 1, 3);
+                    set_sprite(ppu, 0, 16, 8, 1);
+                    ppu.write(WX_ADDR, wx);
+                    ppu.write(WY_ADDR, 0);
+                    ppu.write(SCX_ADDR, scx);
+                });
+                assert_eq!(
+                    line(&ppu, 10),
+                    [7, 7, 7, 7, 7, 7, 7, 7, 0, 0],
+                    "wx {} scx {}",
+                    wx,
+                    scx
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dmg_sprite_priority_by_x() {
+        let ppu = run(0, |ppu| {
+            fill_tile(ppu, 1, 1);
+            fill_tile(ppu, 2, 2);
+            // OAM 0 covers x -2 to 5, OAM 1 with the smaller x covers -4 to 3
+            set_sprite(ppu, 0, 16, 6, 1);
+            set_sprite(ppu, 1, 16, 4, 2);
+        });
+        assert_eq!(line(&ppu, 7), [6, 6, 6, 6, 5, 5, 0]);
+    }
+
+    #[test]
+    fn stat_rising_edge() {
+        // mode 2 only: one request per line
+        let mut ppu = run(0, |ppu| ppu.write(STAT_ADDR, OAM_INTERRUPT));
+        let expected: Vec<_> = (1..144).chain([0]).map(|ly| (ly, 2)).collect();
+        assert_eq!(stat_requests(&mut ppu), expected);
+
+        // mode 0 and 2: the line stays high from mode 0 into mode 2 of the next line, so
+        // only entering mode 0 and the mode 2 after VBlank fire
+        let mut ppu = run(0, |ppu| {
+            ppu.write(STAT_ADDR, HBLANK_INTERRUPT | OAM_INTERRUPT)
+        });
+        let expected: Vec<_> = (0..144).map(|ly| (ly, 0)).chain([(0, 2)]).collect();
+        assert_eq!(stat_requests(&mut ppu), expected);
+    }
+
+    #[test]
+    fn stat_blocked_by_lyc() {
+        // the coincidence keeps the line high from mode 0 of line 4 through line 5
+        let mut ppu = run(0, |ppu| {
+            ppu.write(STAT_ADDR, HBLANK_INTERRUPT | LYC_LY_INTERRUPT);
+            ppu.write(LYC_ADDR, 5);
+        });
+        let expected: Vec<_> = (0..144).filter(|&ly| ly != 5).map(|ly| (ly, 0)).collect();
+        assert_eq!(stat_requests(&mut ppu), expected);
+    }
+
+    #[test]
+    fn ly_153_reads_0_early() {
+        let mut ppu = run(0, |ppu| ppu.write(STAT_ADDR, LYC_LY_INTERRUPT));
+        let mut interrupts = Interrupts::default();
+        for _ in 0..456 * 153 {
+            ppu.emulate_cycle(&mut interrupts, 1);
+        }
+        interrupts.interrupt_flags = 0;
+        // LYC 0 matches 4 dots into line 153, and the line stays high through line 0
+        for _ in 0..4 {
+            assert_eq!(ppu.read(LY_ADDR), 153);
+            assert_eq!(interrupts.interrupt_flags, 0);
+            ppu.emulate_cycle(&mut interrupts, 1);
+        }
+        assert_eq!(ppu.read(LY_ADDR), 0);
+        assert_eq!(
+            ppu.read(STAT_ADDR) & (LYC_LY_COINCIDENCE | 3),
+            LYC_LY_COINCIDENCE | 1
+        );
+        assert_eq!(interrupts.interrupt_flags, interrupt::LCD_STAT);
+        assert_eq!(stat_requests(&mut ppu), [(0, 1)]);
+    }
+
+    #[test]
+    fn lcd_off_and_on() {
+        let mut ppu = run(0, |ppu| fill_tile(ppu, 0, 3));
+        let lcdc = ppu.read(LCDC_ADDR);
+        let mut interrupts = Interrupts::default();
+        ppu.draw();
+        assert!(ppu.pixel_buffer().iter().all(|&c| c == 0x00));
+
+        // off in mode 3 of line 50: LY and mode reset, a blank screen is presented
+        for _ in 0..(456 * 50 + 100) / 4 {
+            ppu.emulate_cycle(&mut interrupts, 4);
+        }
+        assert_eq!(ppu.read(STAT_ADDR) & 3, 3);
+        ppu.write(LCDC_ADDR, lcdc & !PPU_ENABLE);
+        assert_eq!(ppu.read(LY_ADDR), 0);
+        assert_eq!(ppu.read(STAT_ADDR) & 3, 0);
+        assert!(ppu.pixel_buffer().iter().all(|&c| c == 0xFF));
+        for _ in 0..70224 / 4 {
+            assert!(!ppu.emulate_cycle(&mut interrupts, 4));
+        }
+        assert_eq!(ppu.read(LY_ADDR), 0);
+
+        // on: the first line starts in mode 0 without an OAM scan
+        ppu.write(LCDC_ADDR, lcdc);
+        for _ in 0..79 {
+            ppu.emulate_cycle(&mut interrupts, 1);
+            assert_eq!(ppu.read(STAT_ADDR) & 3, 0);
+        }
+        ppu.emulate_cycle(&mut interrupts, 1);
+        assert_eq!(ppu.read(STAT_ADDR) & 3, 3);
+
+        // the first frame is not presented, the screen stays blank until the second
+        let vsyncs: Vec<_> = (80..70224 * 2)
+            .filter(|_| ppu.emulate_cycle(&mut interrupts, 1))
+            .collect();
+        assert_eq!(vsyncs, [70224 * 2 - 1]);
+        assert!(ppu.pixel_buffer().iter().all(|&c| c == 0xFF));
+        ppu.draw();
+        assert!(ppu.pixel_buffer().iter().all(|&c| c == 0x00));
+    }
+
+    // dots in mode 3 on line 4 of the next frame
+    fn drawing_dots(ppu: &mut Ppu) -> usize {
+        let mut interrupts = Interrupts::default();
+        (0..456 * 5)
+            .filter(|_| {
+                ppu.emulate_cycle(&mut interrupts, 1);
+                ppu.read(LY_ADDR) == 4 && ppu.read(STAT_ADDR) & 3 == 3
+            })
+            .count()
+    }
+
+    #[test]
+    fn hidden_layers() {
+        // BG color 1, window color 2 from x 80, sprites color 3 at x 0-7 and 20-27
+        let setup = |layers: Layers| {
+            move |ppu: &mut Ppu| {
+                fill_tile(ppu, 0, 1);
+                fill_tile(ppu, 1, 3);
+                fill_tile(ppu, 2, 2);
+                for addr in 0x9C00..0x9C20 {
+                    ppu.write(addr, 2);
+                }
+                ppu.write(WX_ADDR, 87);
+                ppu.write(WY_ADDR, 0);
+                set_sprite(ppu, 0, 16, 8, 1);
+                set_sprite(ppu, 1, 16, 28, 1);
+                ppu.layers = layers;
+            }
+        };
+        let lcdc = WINDOW_ENABLE | WINDOW_TILE_MAP;
+        let expected = |sprite0: u8, bg: u8, sprite1: u8, window: u8| {
+            let mut line = vec![bg; 88];
+            line[..8].fill(sprite0);
+            line[20..28].fill(sprite1);
+            line[80..].fill(window);
+            line
+        };
+        let mut one_hidden = Layers::default();
+        one_hidden.set_sprite_visible(1, false);
+        for (layers, pixels) in [
+            (Layers::default(), expected(7, 1, 7, 2)),
+            (
+                Layers {
+                    bg: false,
+                    ..Layers::default()
+                },
+                expected(7, 0, 7, 2),
+            ),
+            (
+                Layers {
+                    window: false,
+                    ..Layers::default()
+                },
+                expected(7, 1, 7, 0),
+            ),
+            (
+                Layers {
+                    sprites: false,
+                    ..Layers::default()
+                },
+                expected(1, 1, 1, 2),
+            ),
+            (one_hidden, expected(7, 1, 1, 2)),
+        ] {
+            let mut ppu = run(lcdc, setup(layers));
+            assert_eq!(line(&ppu, 88), pixels, "{:?}", layers);
+            // the sprite and window fetches still take place
+            let mut shown = run(lcdc, setup(Layers::default()));
+            assert_eq!(
+                drawing_dots(&mut ppu),
+                drawing_dots(&mut shown),
+                "{:?}",
+                layers
+            );
+        }
     }
 }
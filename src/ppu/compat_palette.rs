@@ -0,0 +1,330 @@
+// colorization of DMG games by the CGB boot ROM, RGB888 colors for BG, OBJ0 and OBJ1
+// https://gbdev.io/pandocs/Power_Up_Sequence.html#compatibility-palettes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CompatPalette {
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4],
+}
+
+const fn palette(bg: [u32; 4], obj0: [u32; 4], obj1: [u32; 4]) -> CompatPalette {
+    CompatPalette { bg, obj0, obj1 }
+}
+
+const BROWN: [u32; 4] = [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000];
+const RED: [u32; 4] = [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000];
+const DARK_BROWN: [u32; 4] = [0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108];
+const GREEN: [u32; 4] = [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000];
+const BLUE: [u32; 4] = [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000];
+const DARK_BLUE: [u32; 4] = [0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000];
+const GRAYSCALE: [u32; 4] = [0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000];
+const PASTEL: [u32; 4] = [0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000];
+const ORANGE: [u32; 4] = [0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000];
+const YELLOW: [u32; 4] = [0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000];
+const LIME: [u32; 4] = [0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000];
+const DARK_GREEN: [u32; 4] = [0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000];
+const INVERTED: [u32; 4] = [0x000000, 0x008484, 0xFFDE00, 0xFFFFFF];
+
+// selected by holding a direction (and A or B) while the boot logo is shown
+pub const MANUAL_PALETTES: [(&str, CompatPalette); 12] = [
+    ("up", palette(BROWN, BROWN, BROWN)),
+    ("up-a", palette(RED, GREEN, BLUE)),
+    ("up-b", palette(DARK_BROWN, DARK_BROWN, DARK_BROWN)),
+    ("left", palette(BLUE, RED, GREEN)),
+    ("left-a", palette(DARK_BLUE, RED, BROWN)),
+    ("left-b", palette(GRAYSCALE, GRAYSCALE, GRAYSCALE)),
+    ("down", palette(PASTEL, PASTEL, PASTEL)),
+    ("down-a", palette(ORANGE, ORANGE, ORANGE)),
+    ("down-b", palette(YELLOW, BLUE, GREEN)),
+    ("right", palette(LIME, LIME, LIME)),
+    ("right-a", palette(DARK_GREEN, RED, RED)),
+    ("right-b", palette(INVERTED, INVERTED, INVERTED)),
+];
+
+// games without an entry in the title table (same as right + A)
+pub const DEFAULT: CompatPalette = palette(DARK_GREEN, RED, RED);
+
+// palettes of the CGB boot ROM, RGB555
+#[rustfmt::skip]
+const BOOT_COLORS: [u16; 30 * 4] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, // 0
+    0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000,
+    0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000,
+    0x7FFF, 0x5294, 0x294A, 0x0000, // 5
+    0x7FFF, 0x03FF, 0x012F, 0x0000,
+    0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000,
+    0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B, // 10
+    0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000,
+    0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000,
+    0x036A, 0x021F, 0x03FF, 0x7FFF, // 15
+    0x7FFF, 0x01DF, 0x0112, 0x0000,
+    0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000,
+    0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000, // 20
+    0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00,
+    0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000,
+    0x03FF, 0x001F, 0x000C, 0x0000, // 25
+    0x7FFF, 0x033F, 0x0193, 0x0000,
+    0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000,
+    0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+// palette combinations of the boot ROM: offsets in BOOT_COLORS of the OBJ0, OBJ1 and BG
+// colors. a few of them start in the middle of a palette, like in the boot ROM
+const fn comb(obj0: usize, obj1: usize, bg: usize) -> [usize; 3] {
+    [obj0 * 4, obj1 * 4, bg * 4]
+}
+
+const COMBINATIONS: [[usize; 3]; 51] = [
+    comb(4, 4, 29), // right + A
+    comb(18, 18, 18),
+    comb(20, 20, 20),
+    comb(24, 24, 24),
+    comb(9, 9, 9),
+    comb(0, 0, 0), // 5
+    comb(27, 27, 27),
+    comb(5, 5, 5),
+    comb(12, 12, 12),
+    comb(26, 26, 26),
+    comb(16, 8, 8), // 10
+    comb(4, 28, 28),
+    comb(4, 2, 2),
+    comb(3, 4, 4),
+    comb(4, 29, 29),
+    comb(28, 4, 28), // 15
+    comb(2, 17, 2),
+    comb(16, 16, 8),
+    comb(4, 4, 7),
+    comb(4, 4, 18),
+    comb(4, 4, 20), // 20
+    comb(19, 19, 9),
+    [4 * 4 - 1, 4 * 4 - 1, 11 * 4],
+    comb(17, 17, 2),
+    comb(4, 4, 2),
+    comb(4, 4, 3), // 25
+    comb(28, 28, 0),
+    comb(3, 3, 0),
+    comb(0, 0, 1),
+    comb(18, 22, 18),
+    comb(20, 22, 20), // 30
+    comb(24, 22, 24),
+    comb(16, 22, 8),
+    comb(17, 4, 13),
+    [28 * 4 - 1, 0, 14 * 4],
+    [28 * 4 - 1, 4 * 4, 15 * 4], // 35
+    comb(19, 22, 9),
+    comb(16, 28, 10),
+    comb(4, 23, 28),
+    comb(17, 22, 2),
+    comb(4, 0, 2), // 40
+    comb(4, 28, 3),
+    comb(28, 3, 0),
+    comb(3, 28, 4),
+    comb(21, 28, 4),
+    comb(3, 28, 0), // 45
+    comb(25, 3, 28),
+    comb(0, 28, 8),
+    comb(4, 3, 28),
+    comb(28, 3, 6),
+    comb(4, 28, 29), // 50
+];
+
+// title checksum, 4th letter of the title for checksums shared by several games, combination.
+// searched in order like the boot ROM, the first match wins.
+// two entries have bit 7 set in the boot ROM, it is ignored here
+#[rustfmt::skip]
+const TITLE_PALETTES: [(u8, Option<u8>, u8); 94] = [
+    (0x00, None, 0),
+    (0x88, None, 4),  // ALLEY WAY
+    (0x16, None, 5),  // YAKUMAN
+    (0x36, None, 35), // BASEBALL, GAME&WATCH 2
+    (0xD1, None, 34), // TENNIS
+    (0xDB, None, 3),  // TETRIS
+    (0xF2, None, 31), // QIX
+    (0x3C, None, 15), // DR.MARIO
+    (0x8C, None, 10), // RADARMISSION
+    (0x92, None, 5),  // F1RACE
+    (0x3D, None, 19), // YOSSY NO TAMAGO
+    (0x5C, None, 36),
+    (0x58, None, 7),  // X
+    (0xC9, None, 37), // MARIOLAND2
+    (0x3E, None, 30), // YOSSY NO COOKIE
+    (0x70, None, 44), // ZELDA
+    (0x1D, None, 21),
+    (0x59, None, 32),
+    (0x69, None, 31), // TETRIS FLASH
+    (0x19, None, 20), // DONKEY KONG
+    (0x35, None, 5),  // MARIO'S PICROSS
+    (0xA8, None, 33),
+    (0x14, None, 13), // POKEMON RED, GAMEBOYCAMERA G
+    (0xAA, None, 14), // POKEMON GREEN
+    (0x75, None, 5),  // PICROSS 2
+    (0x95, None, 29), // YOSSY NO PANEPON
+    (0x99, None, 5),  // KIRAKIRA KIDS
+    (0x34, None, 18), // GAMEBOY GALLERY
+    (0x6F, None, 9),  // POCKETCAMERA
+    (0x15, None, 3),
+    (0xFF, None, 2),  // BALLOON KID
+    (0x97, None, 26), // KINGOFTHEZOO
+    (0x4B, None, 25), // DMG FOOTBALL
+    (0x90, None, 25), // WORLD CUP
+    (0x17, None, 41), // OTHELLO
+    (0x10, None, 42), // SUPER RC PRO-AM
+    (0x39, None, 26), // DYNABLASTER
+    (0xF7, None, 45), // BOY AND BLOB GB2
+    (0xF6, None, 42), // MEGAMAN
+    (0xA2, None, 45), // STAR WARS-NOA
+    (0x49, None, 36),
+    (0x4E, None, 38), // WAVERACE
+    (0x43, None, 26),
+    (0x68, None, 42), // LOLO2
+    (0xE0, None, 30), // YOSHI'S COOKIE
+    (0x8B, None, 41), // MYSTIC QUEST
+    (0xF0, None, 34),
+    (0xCE, None, 34), // TOPRANKINGTENNIS
+    (0x0C, None, 5),  // MANSELL
+    (0x29, None, 42), // MEGAMAN3
+    (0xE8, None, 6),  // SPACE INVADERS
+    (0xB7, None, 5),  // GAME&WATCH
+    (0x86, None, 33), // DONKEYKONGLAND95
+    (0x9A, None, 25), // ASTEROIDS/MISCMD
+    (0x52, None, 42), // STREET FIGHTER 2
+    (0x01, None, 42), // DEFENDER/JOUST
+    (0x9D, None, 40), // KILLERINSTINCT95
+    (0x71, None, 2),  // TETRIS BLAST
+    (0x9C, None, 16), // PINOCCHIO
+    (0xBD, None, 25),
+    (0x5D, None, 42), // BA.TOSHINDEN
+    (0x6D, None, 42), // NETTOU KOF 95
+    (0x67, None, 5),
+    (0x3F, None, 0),  // TETRIS PLUS
+    (0xE8, None, 39), // DONKEYKONGLAND 3, shadowed by SPACE INVADERS
+    // checksums shared by several titles
+    (0xB3, Some(b'B'), 36),
+    (0x46, Some(b'E'), 22), // SUPER MARIOLAND
+    (0x28, Some(b'F'), 25), // GOLF
+    (0xA5, Some(b'A'), 6),  // SOLARSTRIKER
+    (0xC6, Some(b'A'), 32), // GBWARS
+    (0xD3, Some(b'R'), 12), // KAERUNOTAMENI
+    (0x27, Some(b'B'), 36),
+    (0x61, Some(b'E'), 11), // POKEMON BLUE
+    (0x18, Some(b'K'), 39), // DONKEYKONGLAND
+    (0x66, Some(b'E'), 18), // GAMEBOY GALLERY2
+    (0x6A, Some(b'K'), 39), // DONKEYKONGLAND 2
+    (0xBF, Some(b' '), 24), // KID ICARUS
+    (0x0D, Some(b'R'), 31), // TETRIS2
+    (0xF4, Some(b'-'), 50),
+    (0xB3, Some(b'U'), 17), // MOGURANYA
+    (0x46, Some(b'R'), 46),
+    (0x28, Some(b'A'), 6),  // GALAGA&GALAXIAN
+    (0xA5, Some(b'R'), 27), // BT2RAGNAROKWORLD
+    (0xC6, Some(b' '), 0),  // KEN GRIFFEY JR
+    (0xD3, Some(b'I'), 47),
+    (0x27, Some(b'N'), 41), // MAGNETIC SOCCER
+    (0x61, Some(b'A'), 41), // VEGAS STAKES
+    (0x18, Some(b'I'), 0),
+    (0x66, Some(b'L'), 0),  // MILLI/CENTI/PEDE
+    (0x6A, Some(b'I'), 19), // MARIO & YOSHI
+    (0xBF, Some(b'C'), 34), // SOCCER
+    (0x0D, Some(b'E'), 23), // POKEBOM
+    (0xF4, Some(b' '), 18), // G&W GALLERY
+    (0xB3, Some(b'R'), 29), // TETRIS ATTACK
+];
+
+// RGB555 to RGB888
+const fn rgb888(color: u16) -> u32 {
+    const fn expand(c: u16) -> u32 {
+        ((c & 0x1F) as u32 * 255 + 15) / 31
+    }
+    expand(color) << 16 | expand(color >> 5) << 8 | expand(color >> 10)
+}
+
+fn combination(idx: usize) -> CompatPalette {
+    let colors = |offset: usize| std::array::from_fn(|i| rgb888(BOOT_COLORS[offset + i]));
+    let [obj0, obj1, bg] = COMBINATIONS[idx];
+    palette(colors(bg), colors(obj0), colors(obj1))
+}
+
+pub fn by_name(name: &str) -> Option<CompatPalette> {
+    MANUAL_PALETTES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(_, palette)| palette)
+}
+
+// key: title checksum and 4th letter, None for games not licensed by Nintendo
+pub fn lookup(key: Option<(u8, u8)>) -> CompatPalette {
+    let Some((checksum, letter)) = key else {
+        return DEFAULT;
+    };
+    TITLE_PALETTES
+        .iter()
+        .find(|&&(c, l, _)| c == checksum && l.is_none_or(|l| l == letter))
+        .map_or(DEFAULT, |&(_, _, idx)| combination(idx as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the key of a title licensed by Nintendo, without maker code and CGB flag
+    fn key(title: &str) -> Option<(u8, u8)> {
+        let checksum = title.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        Some((checksum, title.as_bytes()[3]))
+    }
+
+    #[test]
+    fn title_checksum() {
+        assert_eq!(key("POKEMON RED"), Some((0x14, b'E')));
+        let red = lookup(key("POKEMON RED"));
+        assert_eq!(red, palette(RED, GREEN, RED));
+        assert_eq!(lookup(key("TETRIS")), palette(ORANGE, ORANGE, ORANGE));
+    }
+
+    #[test]
+    fn checksum_collision_resolved_by_letter() {
+        // both sum to 0x61
+        assert_eq!(key("POKEMON BLUE").unwrap().0, 0x61);
+        assert_eq!(key("VEGAS STAKES").unwrap().0, 0x61);
+        assert_eq!(lookup(key("POKEMON BLUE")), palette(BLUE, RED, BLUE));
+        assert_eq!(lookup(key("VEGAS STAKES")), palette(GREEN, RED, BLUE));
+        // same checksum, 4th letter not in the table
+        assert_eq!(lookup(Some((0x61, b'Z'))), DEFAULT);
+    }
+
+    #[test]
+    fn unknown_titles() {
+        assert_eq!(lookup(None), DEFAULT);
+        assert_eq!(lookup(Some((0x02, b'A'))), DEFAULT);
+    }
+
+    #[test]
+    fn manual_palettes_are_boot_rom_combinations() {
+        assert_eq!(combination(0), DEFAULT);
+        for (name, idx) in [
+            ("up", 5),
+            ("up-a", 43),
+            ("left", 48),
+            ("left-a", 40),
+            ("left-b", 7),
+            ("down", 8),
+            ("down-a", 3),
+            ("down-b", 49),
+            ("right", 1),
+            ("right-a", 0),
+            ("right-b", 6),
+        ] {
+            assert_eq!(by_name(name), Some(combination(idx)), "{}", name);
+        }
+    }
+}
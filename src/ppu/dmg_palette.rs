@@ -0,0 +1,113 @@
+// colorization of the DMG shades, RGB888 colors for BG, OBJ0 and OBJ1.
+// different colors per layer give a "pseudo-color" picture
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DmgPalette {
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4],
+}
+
+const fn mono(colors: [u32; 4]) -> DmgPalette {
+    DmgPalette {
+        bg: colors,
+        obj0: colors,
+        obj1: colors,
+    }
+}
+
+pub const PRESETS: [(&str, DmgPalette); 4] = [
+    ("gray", mono([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000])),
+    // original DMG, pea green
+    ("dmg", mono([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F])),
+    ("pocket", mono([0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F])),
+    // Game Boy Light with the backlight on
+    ("light", mono([0x00B581, 0x009A71, 0x00694A, 0x004F3B])),
+];
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        PRESETS[0].1
+    }
+}
+
+impl DmgPalette {
+    // a preset name, 4 comma separated RGB hex colors (lightest first) for all layers,
+    // or 12 colors for BG, OBJ0 and OBJ1
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some(&(_, palette)) = PRESETS.iter().find(|(name, _)| *name == s) {
+            return Some(palette);
+        }
+        let colors = s
+            .split(',')
+            .map(|c| u32::from_str_radix(c.trim().trim_start_matches('#'), 16).ok())
+            .collect::<Option<Vec<u32>>>()?;
+        if colors.iter().any(|&c| c > 0xFFFFFF) {
+            return None;
+        }
+        match colors.len() {
+            4 => Some(mono(colors[0..4].try_into().unwrap())),
+            12 => Some(Self {
+                bg: colors[0..4].try_into().unwrap(),
+                obj0: colors[4..8].try_into().unwrap(),
+                obj1: colors[8..12].try_into().unwrap(),
+            }),
+            _ => None,
+        }
+    }
+
+    // index: layer (0: BG, 1: OBJ0, 2: OBJ1) << 2 | shade
+    pub fn color(&self, index: u8) -> [u8; 3] {
+        let colors = match index >> 2 {
+            0 => &self.bg,
+            1 => &self.obj0,
+            _ => &self.obj1,
+        };
+        let [_, r, g, b] = colors[(index & 0b11) as usize].to_be_bytes();
+        [r, g, b]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_presets() {
+        for (name, palette) in PRESETS {
+            assert_eq!(DmgPalette::parse(name), Some(palette));
+        }
+        assert_eq!(
+            DmgPalette::parse("dmg").unwrap().color(0),
+            [0x9B, 0xBC, 0x0F]
+        );
+    }
+
+    #[test]
+    fn parse_colors() {
+        let palette = DmgPalette::parse("#E0F8D0, 88c070,#346856,081820").unwrap();
+        assert_eq!(palette, mono([0xE0F8D0, 0x88C070, 0x346856, 0x081820]));
+
+        let colors: Vec<String> = (0..12).map(|i| format!("{:06X}", i * 0x111111)).collect();
+        let palette = DmgPalette::parse(&colors.join(",")).unwrap();
+        assert_eq!(palette.bg, [0x000000, 0x111111, 0x222222, 0x333333]);
+        assert_eq!(palette.obj0, [0x444444, 0x555555, 0x666666, 0x777777]);
+        assert_eq!(palette.obj1, [0x888888, 0x999999, 0xAAAAAA, 0xBBBBBB]);
+        // OBJ1 shade 2
+        assert_eq!(palette.color(2 << 2 | 2), [0xAA; 3]);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for s in [
+            "",
+            "green",
+            "FFFFFF,AAAAAA,555555",
+            "FFFFFF,AAAAAA,555555,000000,000000",
+            "FFFFFF,AAAAAA,555555,GGGGGG",
+            "FFFFFF,AAAAAA,555555,1000000",
+            "FFFFFF,,555555,000000",
+        ] {
+            assert_eq!(DmgPalette::parse(s), None, "{:?}", s);
+        }
+    }
+}
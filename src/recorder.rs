@@ -0,0 +1,117 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Sender},
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    apu::SAMPLE_RATE,
+    avi::{self, AviWriter},
+    gameboy::CPU_CLOCK_HZ,
+};
+
+// 154 lines * 456 dots, 59.7275 Hz
+pub const FRAME_CLOCKS: u32 = 70224;
+
+enum Message {
+    Frame(Box<[u8]>, Vec<i16>),
+    Finish,
+}
+
+// lossless gameplay recording to AVI, encoded and written on a separate thread
+pub struct Recorder {
+    sender: Sender<Message>,
+    thread: JoinHandle<io::Result<()>>,
+    last_frame: Box<[u8]>,
+    frames: u64,
+    samples: u64,      // stereo sample frames sent
+    pending: Vec<i16>, // audio not sent with a frame yet
+}
+
+impl Recorder {
+    // files after the first 1 GiB are numbered, e.g. play.avi, play-1.avi
+    pub fn start(path: &Path, width: usize, height: usize) -> io::Result<Self> {
+        let open = move |path: &Path| {
+            AviWriter::create(
+                path,
+                width,
+                height,
+                CPU_CLOCK_HZ as u32,
+                FRAME_CLOCKS,
+                SAMPLE_RATE as u32,
+            )
+        };
+        // fail early on an unwritable path
+        let mut avi = open(path)?;
+        let path = path.to_path_buf();
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut segment = 0;
+            while let Ok(Message::Frame(pixels, samples)) = receiver.recv() {
+                if avi.size() + (pixels.len() + samples.len() * 2) as u64 + 64 > avi::MAX_SIZE {
+                    avi.finish()?;
+                    segment += 1;
+                    avi = open(&segment_path(&path, segment))?;
+                }
+                avi.write_frame(&pixels)?;
+                avi.write_audio(&samples)?;
+            }
+            avi.finish()
+        });
+        Ok(Self {
+            sender,
+            thread,
+            last_frame: vec![0; width * height * 3].into(),
+            frames: 0,
+            samples: 0,
+            pending: vec![],
+        })
+    }
+
+    // a frame drawn at VBlank with the audio output since the last frame
+    pub fn frame(&mut self, pixels: Box<[u8]>, samples: &[f32]) {
+        self.push_audio(samples);
+        self.last_frame = pixels.clone();
+        self.send(pixels);
+    }
+
+    // audio while no frames are drawn (LCD off), the last frame is repeated
+    // so that the video does not fall behind the audio
+    pub fn audio(&mut self, samples: &[f32]) {
+        self.push_audio(samples);
+        // audio more than 1.5 frames ahead of the video
+        while (self.samples + self.pending.len() as u64 / 2) * CPU_CLOCK_HZ as u64 * 2
+            > (2 * self.frames + 3) * FRAME_CLOCKS as u64 * SAMPLE_RATE as u64
+        {
+            self.send(self.last_frame.clone());
+        }
+    }
+
+    fn push_audio(&mut self, samples: &[f32]) {
+        self.pending.extend(
+            samples
+                .iter()
+                .map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+        );
+    }
+
+    fn send(&mut self, pixels: Box<[u8]>) {
+        let samples = std::mem::take(&mut self.pending);
+        self.frames += 1;
+        self.samples += samples.len() as u64 / 2;
+        // the writer thread only stops on an error, reported by stop
+        let _ = self.sender.send(Message::Frame(pixels, samples));
+    }
+
+    pub fn stop(self) -> io::Result<()> {
+        let _ = self.sender.send(Message::Finish);
+        self.thread.join().expect("recorder thread panicked")
+    }
+}
+
+fn segment_path(path: &Path, segment: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-{}.{}", stem, segment, ext))
+}
@@ -0,0 +1,79 @@
+use std::{
+    fs, io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{png, ppu::Ppu};
+
+// PNG captures of the frame buffer, written without the SDL window so they also work headless
+pub struct Screenshots {
+    pub dir: PathBuf,
+    pub window_scale: bool, // scaled as the LCD window currently is, native resolution if not
+}
+
+impl Screenshots {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            window_scale: false,
+        }
+    }
+
+    // the last frame drawn, or the frame in progress if called during mode 3
+    pub fn save(&self, ppu: &Ppu) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        let (width, height) = ppu.frame_size();
+        let scale = if self.window_scale {
+            ppu.window_scale()
+        } else {
+            1
+        };
+        let pixels = upscale(&ppu.pixel_buffer(), width, height, scale);
+        let path = self.dir.join(format!("screenshot-{}.png", timestamp()));
+        png::save(&path, &pixels, width * scale, height * scale)?;
+        Ok(path)
+    }
+}
+
+// nearest neighbor, RGB24
+fn upscale(pixels: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(pixels.len() * scale * scale);
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let i = ((y / scale) * width + x / scale) * 3;
+            ret.extend_from_slice(&pixels[i..i + 3]);
+        }
+    }
+    ret
+}
+
+// UTC, YYYYMMDD-hhmmss-mmm
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before the unix epoch");
+    let secs = now.as_secs();
+    let (days, time) = (secs / 86400, secs % 86400);
+    // days since the epoch to the civil date
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
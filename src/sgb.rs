@@ -0,0 +1,581 @@
+use crate::ppu::{LCD_HEIGHT, LCD_PIXELS, LCD_WIDTH};
+
+// Super Game Boy: commands sent by the game through the joypad register, colorization
+// of the DMG shades with 4 palettes per 8x8 cell and the border around the screen
+// https://gbdev.io/pandocs/SGB_Functions.html
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+// top left of the game screen in the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// attribute map: one palette per 8x8 cell
+const ATTR_WIDTH: usize = LCD_WIDTH / 8;
+const ATTR_HEIGHT: usize = LCD_HEIGHT / 8;
+const ATTR_FILE_SIZE: usize = ATTR_WIDTH * ATTR_HEIGHT / 4; // 2 bits per cell
+const ATTR_FILES: usize = 45;
+
+// commands
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    None,
+    Freeze, // keep showing the current picture
+    Black,
+    Color0, // color 0 of palette 0
+}
+
+// VRAM transfers copy 4 KiB from the next frame shown on the screen
+#[derive(Clone, Copy)]
+enum Transfer {
+    Palettes,
+    Tiles(usize), // first tile, 0x00 or 0x80
+    Border,
+    AttrFiles,
+}
+
+pub struct Sgb {
+    palettes: [[u16; 4]; 4], // RGB555, color 0 is shared
+    system_palettes: Box<[[u16; 4]; 512]>,
+    attrs: [u8; ATTR_WIDTH * ATTR_HEIGHT],
+    attr_files: Box<[u8; ATTR_FILE_SIZE * ATTR_FILES]>,
+    border_tiles: Box<[u8; 256 * 32]>, // SNES 4bpp
+    border_map: Box<[u16; 32 * 28]>,   // tile, palette (4-7), flips
+    border_palettes: [[u16; 16]; 4],
+    mask: Mask,
+    transfer: Option<Transfer>,
+    screen: Box<[u8; LCD_PIXELS]>, // shades of the last frame
+    pub players: u8,               // MLT_REQ
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        let gray = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+        Self {
+            palettes: [gray; 4],
+            system_palettes: Box::new([gray; 512]),
+            attrs: [0; ATTR_WIDTH * ATTR_HEIGHT],
+            attr_files: Box::new([0; ATTR_FILE_SIZE * ATTR_FILES]),
+            border_tiles: Box::new([0; 256 * 32]),
+            border_map: Box::new([0; 32 * 28]),
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::None,
+            transfer: None,
+            screen: Box::new([0; LCD_PIXELS]),
+            players: 1,
+        }
+    }
+
+    // data: all packets of a command, the first byte is command << 3 | packets
+    pub fn command(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, data),
+            PAL23 => self.set_palettes(2, 3, data),
+            PAL03 => self.set_palettes(0, 3, data),
+            PAL12 => self.set_palettes(1, 2, data),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            PAL_SET => {
+                for i in 0..4 {
+                    let idx = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x1FF;
+                    self.palettes[i] = self.system_palettes[idx as usize];
+                }
+                for i in 1..4 {
+                    self.palettes[i][0] = self.palettes[0][0];
+                }
+                if data[9] & 0x80 != 0 {
+                    self.set_attr_file(data[9] & 0x3F);
+                }
+                if data[9] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            PAL_TRN => self.transfer = Some(Transfer::Palettes),
+            MLT_REQ => {
+                self.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                }
+            }
+            CHR_TRN => self.transfer = Some(Transfer::Tiles((data[1] as usize & 1) << 7)),
+            PCT_TRN => self.transfer = Some(Transfer::Border),
+            ATTR_TRN => self.transfer = Some(Transfer::AttrFiles),
+            ATTR_SET => {
+                self.set_attr_file(data[1] & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            MASK_EN => {
+                self.mask = match data[1] & 0b11 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::None,
+                }
+            }
+            _ => {} // sound, SNES program transfers and the boot ROM header are not supported
+        }
+    }
+
+    fn set_palettes(&mut self, p0: usize, p1: usize, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]) & 0x7FFF;
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(1);
+        }
+        for c in 1..4 {
+            self.palettes[p0][c] = color(1 + c * 2);
+            self.palettes[p1][c] = color(7 + c * 2);
+        }
+    }
+
+    fn set_attr(&mut self, x: usize, y: usize, palette: u8) {
+        if x < ATTR_WIDTH && y < ATTR_HEIGHT {
+            self.attrs[y * ATTR_WIDTH + x] = palette & 0b11;
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let (control, palettes) = (set[0] & 0b111, set[1]);
+            let inside = palettes & 0b11;
+            let outside = (palettes >> 4) & 0b11;
+            // with only inside or outside selected, the surrounding line takes that palette too
+            let (control, border) = match control {
+                0b001 => (0b011, inside),
+                0b100 => (0b110, outside),
+                _ => (control, (palettes >> 2) & 0b11),
+            };
+            let [x1, y1, x2, y2] = [set[2], set[3], set[4], set[5]].map(|v| (v & 0x1F) as usize);
+            for y in 0..ATTR_HEIGHT {
+                for x in 0..ATTR_WIDTH {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_line = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    if on_line && control & 0b010 != 0 {
+                        self.set_attr(x, y, border);
+                    } else if within && !on_line && control & 0b001 != 0 {
+                        self.set_attr(x, y, inside);
+                    } else if !within && control & 0b100 != 0 {
+                        self.set_attr(x, y, outside);
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let (n, palette) = ((line & 0x1F) as usize, (line >> 5) & 0b11);
+            if line & 0x80 != 0 {
+                // horizontal line
+                for x in 0..ATTR_WIDTH {
+                    self.set_attr(x, n, palette);
+                }
+            } else {
+                for y in 0..ATTR_HEIGHT {
+                    self.set_attr(n, y, palette);
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on) = (data[1] & 0b11, (data[1] >> 2) & 0b11, (data[1] >> 4) & 0b11);
+        let horizontal = data[1] & 0x40 != 0;
+        let n = (data[2] & 0x1F) as usize;
+        for y in 0..ATTR_HEIGHT {
+            for x in 0..ATTR_WIDTH {
+                let pos = if horizontal { y } else { x };
+                let palette = match pos.cmp(&n) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on,
+                    std::cmp::Ordering::Greater => after,
+                };
+                self.set_attr(x, y, palette);
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = ((data[1] & 0x1F) as usize, (data[2] & 0x1F) as usize);
+        let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min(ATTR_WIDTH * ATTR_HEIGHT);
+        let vertical = data[5] & 1 != 0;
+        for i in 0..count {
+            let Some(&byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            self.set_attr(x, y, byte >> (6 - (i % 4) * 2));
+            if vertical {
+                y += 1;
+                if y == ATTR_HEIGHT {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == ATTR_WIDTH {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn set_attr_file(&mut self, file: u8) {
+        let file = file as usize;
+        if file >= ATTR_FILES {
+            return;
+        }
+        let data = &self.attr_files[file * ATTR_FILE_SIZE..(file + 1) * ATTR_FILE_SIZE];
+        for i in 0..ATTR_WIDTH * ATTR_HEIGHT {
+            self.attrs[i] = (data[i / 4] >> (6 - (i % 4) * 2)) & 0b11;
+        }
+    }
+
+    // 4 KiB sent as the tile data of the first 256 tiles of the screen, 20 tiles per row
+    fn vram_data(shades: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(0x1000);
+        for tile in 0..256 {
+            let (tx, ty) = (tile % ATTR_WIDTH, tile / ATTR_WIDTH);
+            for row in 0..8 {
+                let (mut low, mut high) = (0, 0);
+                for col in 0..8 {
+                    let shade = shades[(ty * 8 + row) * LCD_WIDTH + tx * 8 + col];
+                    low |= (shade & 1) << (7 - col);
+                    high |= ((shade >> 1) & 1) << (7 - col);
+                }
+                data.push(low);
+                data.push(high);
+            }
+        }
+        data
+    }
+
+    fn vram_transfer(&mut self, transfer: Transfer, shades: &[u8]) {
+        let data = Self::vram_data(shades);
+        let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        match transfer {
+            Transfer::Palettes => {
+                for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+                    for (c, color) in palette.iter_mut().enumerate() {
+                        *color = word(i * 8 + c * 2) & 0x7FFF;
+                    }
+                }
+            }
+            Transfer::Tiles(first) => {
+                self.border_tiles[first * 32..(first + 128) * 32].copy_from_slice(&data);
+            }
+            Transfer::Border => {
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = word(i * 2);
+                }
+                for (p, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (c, color) in palette.iter_mut().enumerate() {
+                        *color = word(0x800 + p * 32 + c * 2) & 0x7FFF;
+                    }
+                }
+            }
+            Transfer::AttrFiles => {
+                self.attr_files
+                    .copy_from_slice(&data[..ATTR_FILE_SIZE * ATTR_FILES]);
+            }
+        }
+    }
+
+    // called for each frame with the DMG shades (indices into BGP/OBP) of the screen
+    pub fn frame(&mut self, shades: &[u8]) {
+        let shades: Vec<u8> = shades.iter().map(|&shade| shade & 0b11).collect();
+        if let Some(transfer) = self.transfer.take() {
+            self.vram_transfer(transfer, &shades);
+        }
+        if self.mask != Mask::Freeze {
+            self.screen.copy_from_slice(&shades);
+        }
+    }
+
+    fn border_pixel(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = self.border_map[(y / 8) * 32 + x / 8];
+        let tile = &self.border_tiles[(entry as usize & 0xFF) * 32..][..32];
+        let col = if entry & 0x4000 != 0 {
+            x & 7
+        } else {
+            7 - (x & 7)
+        };
+        let row = if entry & 0x8000 != 0 {
+            7 - (y & 7)
+        } else {
+            y & 7
+        };
+        let color = [
+            tile[row * 2],
+            tile[row * 2 + 1],
+            tile[16 + row * 2],
+            tile[17 + row * 2],
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |color, (plane, &bits)| {
+            color | ((bits >> col) & 1) << plane
+        });
+        let palette = ((entry >> 10) & 0b11) as usize; // palettes 4-7
+        (color != 0).then(|| self.border_palettes[palette][color as usize])
+    }
+
+    // RGB24 picture of the border with the game screen in it
+    pub fn render(&self) -> Box<[u8]> {
+        let mut pixels = vec![0; SGB_WIDTH * SGB_HEIGHT * 3];
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let (sx, sy) = (x.wrapping_sub(SCREEN_X), y.wrapping_sub(SCREEN_Y));
+                let color = if let Some(color) = self.border_pixel(x, y) {
+                    color
+                } else if sx < LCD_WIDTH && sy < LCD_HEIGHT {
+                    match self.mask {
+                        Mask::Black => 0,
+                        Mask::Color0 => self.palettes[0][0],
+                        Mask::None | Mask::Freeze => {
+                            let palette = self.attrs[(sy / 8) * ATTR_WIDTH + sx / 8];
+                            let shade = self.screen[sy * LCD_WIDTH + sx];
+                            self.palettes[palette as usize][shade as usize]
+                        }
+                    }
+                } else {
+                    self.palettes[0][0]
+                };
+                let i = (y * SGB_WIDTH + x) * 3;
+                pixels[i..i + 3].copy_from_slice(&rgb(color));
+            }
+        }
+        pixels.into()
+    }
+}
+
+fn rgb(color: u16) -> [u8; 3] {
+    [0, 5, 10].map(|shift| {
+        let c = ((color >> shift) & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(sgb: &mut Sgb, cmd: u8, args: &[u8]) {
+        let packets = (1 + args.len()).div_ceil(16);
+        let mut data = vec![0; packets * 16];
+        data[0] = cmd << 3 | packets as u8;
+        data[1..=args.len()].copy_from_slice(args);
+        sgb.command(&data);
+    }
+
+    fn attr(sgb: &Sgb, x: usize, y: usize) -> u8 {
+        sgb.attrs[y * ATTR_WIDTH + x]
+    }
+
+    // screen shades sending data with a VRAM transfer
+    fn shades(data: &[u8]) -> Vec<u8> {
+        let mut shades = vec![0; LCD_PIXELS];
+        for (i, bytes) in data.chunks_exact(2).enumerate() {
+            let (tile, row) = (i / 8, i % 8);
+            let (tx, ty) = (tile % ATTR_WIDTH, tile / ATTR_WIDTH);
+            for col in 0..8 {
+                let (low, high) = ((bytes[0] >> (7 - col)) & 1, (bytes[1] >> (7 - col)) & 1);
+                shades[(ty * 8 + row) * LCD_WIDTH + tx * 8 + col] = high << 1 | low;
+            }
+        }
+        shades
+    }
+
+    // color c of system palette i is i * 4 + c
+    fn transfer_system_palettes(sgb: &mut Sgb) {
+        let data: Vec<u8> = (0..0x800u16).flat_map(|i| i.to_le_bytes()).collect();
+        command(sgb, PAL_TRN, &[]);
+        sgb.frame(&shades(&data));
+    }
+
+    #[test]
+    fn vram_data() {
+        let data: Vec<u8> = (0..0x1000).map(|i| (i * 7) as u8).collect();
+        assert_eq!(Sgb::vram_data(&shades(&data)), data);
+    }
+
+    #[test]
+    fn pal01() {
+        let mut sgb = Sgb::new();
+        let colors: Vec<u8> = (1..=7u16)
+            .flat_map(|c| (c | 0x8000).to_le_bytes())
+            .collect();
+        command(&mut sgb, PAL01, &colors);
+        assert_eq!(sgb.palettes[0], [1, 2, 3, 4]);
+        assert_eq!(sgb.palettes[1], [1, 5, 6, 7]);
+        // color 0 is shared
+        assert_eq!(sgb.palettes[2][0], 1);
+    }
+
+    #[test]
+    fn attr_blk() {
+        let mut sgb = Sgb::new();
+        // inside 1, border 2, outside 3
+        command(&mut sgb, ATTR_BLK, &[1, 0b111, 0b11_10_01, 2, 2, 5, 4]);
+        assert_eq!(attr(&sgb, 3, 3), 1);
+        assert_eq!(attr(&sgb, 2, 2), 2);
+        assert_eq!(attr(&sgb, 5, 3), 2);
+        assert_eq!(attr(&sgb, 4, 4), 2);
+        assert_eq!(attr(&sgb, 0, 0), 3);
+        assert_eq!(attr(&sgb, 6, 3), 3);
+
+        // only inside: the border takes the inside palette, outside is unchanged
+        let mut sgb = Sgb::new();
+        command(&mut sgb, ATTR_BLK, &[1, 0b001, 0b11_10_01, 2, 2, 5, 4]);
+        assert_eq!(attr(&sgb, 3, 3), 1);
+        assert_eq!(attr(&sgb, 2, 2), 1);
+        assert_eq!(attr(&sgb, 0, 0), 0);
+
+        // 3 data sets over 2 packets, the later ones overwrite the earlier ones
+        let mut sgb = Sgb::new();
+        #[rustfmt::skip]
+        command(&mut sgb, ATTR_BLK, &[
+            3,
+            0b111, 0b01_01_01, 0, 0, 3, 3,
+            0b011, 0b10_10, 1, 1, 2, 2,
+            0b010, 0b11_00, 10, 10, 10, 10,
+        ]);
+        assert_eq!(attr(&sgb, 0, 0), 1);
+        assert_eq!(attr(&sgb, 1, 1), 2);
+        assert_eq!(attr(&sgb, 3, 3), 1);
+        assert_eq!(attr(&sgb, 4, 4), 1);
+        assert_eq!(attr(&sgb, 10, 10), 3);
+    }
+
+    #[test]
+    fn attr_lin() {
+        let mut sgb = Sgb::new();
+        // horizontal line 3 with palette 2, then vertical line 5 with palette 1
+        command(&mut sgb, ATTR_LIN, &[2, 0x80 | 2 << 5 | 3, 1 << 5 | 5]);
+        assert_eq!(attr(&sgb, 0, 3), 2);
+        assert_eq!(attr(&sgb, 19, 3), 2);
+        assert_eq!(attr(&sgb, 5, 0), 1);
+        assert_eq!(attr(&sgb, 5, 3), 1);
+        assert_eq!(attr(&sgb, 0, 0), 0);
+    }
+
+    #[test]
+    fn attr_div() {
+        let mut sgb = Sgb::new();
+        // horizontal at line 5: above 2, on the line 3, below 1
+        command(&mut sgb, ATTR_DIV, &[0x40 | 3 << 4 | 2 << 2 | 1, 5]);
+        assert_eq!(attr(&sgb, 0, 4), 2);
+        assert_eq!(attr(&sgb, 10, 5), 3);
+        assert_eq!(attr(&sgb, 19, 17), 1);
+
+        // vertical at column 0
+        command(&mut sgb, ATTR_DIV, &[3 << 4 | 2 << 2 | 1, 0]);
+        assert_eq!(attr(&sgb, 0, 4), 3);
+        assert_eq!(attr(&sgb, 1, 4), 1);
+    }
+
+    #[test]
+    fn attr_chr() {
+        let mut sgb = Sgb::new();
+        // left to right from (18, 0), wrapping to the next line
+        command(&mut sgb, ATTR_CHR, &[18, 0, 4, 0, 0, 0b00_01_10_11]);
+        assert_eq!(attr(&sgb, 18, 0), 0);
+        assert_eq!(attr(&sgb, 19, 0), 1);
+        assert_eq!(attr(&sgb, 0, 1), 2);
+        assert_eq!(attr(&sgb, 1, 1), 3);
+        assert_eq!(attr(&sgb, 2, 1), 0);
+
+        // top to bottom from (0, 16), wrapping to the next column
+        command(&mut sgb, ATTR_CHR, &[0, 16, 3, 0, 1, 0b01_10_11_00]);
+        assert_eq!(attr(&sgb, 0, 16), 1);
+        assert_eq!(attr(&sgb, 0, 17), 2);
+        assert_eq!(attr(&sgb, 1, 0), 3);
+    }
+
+    #[test]
+    fn pal_trn() {
+        let mut sgb = Sgb::new();
+        command(&mut sgb, PAL_TRN, &[]);
+        assert!(sgb.system_palettes[1] != [4, 5, 6, 7]);
+        // copied from the next frame
+        transfer_system_palettes(&mut sgb);
+        assert_eq!(sgb.system_palettes[1], [4, 5, 6, 7]);
+        assert_eq!(sgb.system_palettes[511], [2044, 2045, 2046, 2047]);
+        // only once
+        sgb.frame(&[0; LCD_PIXELS]);
+        assert_eq!(sgb.system_palettes[1], [4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn pal_set() {
+        let mut sgb = Sgb::new();
+        transfer_system_palettes(&mut sgb);
+        command(&mut sgb, MASK_EN, &[2]);
+        // attribute file 1: every cell uses palette 2
+        sgb.attr_files[ATTR_FILE_SIZE..2 * ATTR_FILE_SIZE].fill(0xAA);
+        command(
+            &mut sgb,
+            PAL_SET,
+            &[3, 0, 10, 0, 0xFF, 0x01, 0, 0, 0xC0 | 1],
+        );
+        assert_eq!(sgb.palettes[0], [12, 13, 14, 15]);
+        // color 0 of palette 0 is shared
+        assert_eq!(sgb.palettes[1], [12, 41, 42, 43]);
+        assert_eq!(sgb.palettes[2], [12, 2045, 2046, 2047]);
+        assert_eq!(sgb.palettes[3], [12, 1, 2, 3]);
+        assert!(sgb.attrs.iter().all(|&palette| palette == 2));
+        assert!(sgb.mask == Mask::None);
+    }
+
+    #[test]
+    fn mask_en() {
+        let pixel = |sgb: &Sgb, x: usize, y: usize| {
+            let i = ((SCREEN_Y + y) * SGB_WIDTH + SCREEN_X + x) * 3;
+            sgb.render()[i..i + 3].to_vec()
+        };
+        let mut sgb = Sgb::new();
+        sgb.frame(&[3; LCD_PIXELS]);
+        assert_eq!(pixel(&sgb, 0, 0), [0, 0, 0]);
+
+        // freeze keeps the last frame
+        command(&mut sgb, MASK_EN, &[1]);
+        sgb.frame(&[0; LCD_PIXELS]);
+        assert_eq!(pixel(&sgb, 0, 0), [0, 0, 0]);
+
+        command(&mut sgb, MASK_EN, &[2]);
+        sgb.frame(&[0; LCD_PIXELS]);
+        assert_eq!(pixel(&sgb, 0, 0), [0, 0, 0]);
+
+        command(&mut sgb, MASK_EN, &[3]);
+        sgb.palettes[0][0] = 0x001F;
+        assert_eq!(pixel(&sgb, 10, 10), [0xFF, 0, 0]);
+
+        command(&mut sgb, MASK_EN, &[0]);
+        assert_eq!(pixel(&sgb, 0, 0), [0xFF, 0, 0]);
+        sgb.frame(&[3; LCD_PIXELS]);
+        assert_eq!(pixel(&sgb, 0, 0), [0, 0, 0]);
+    }
+}
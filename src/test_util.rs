@@ -0,0 +1,7 @@
+use std::path::PathBuf;
+
+// file in the temp directory, named by the process so that concurrent test runs
+// do not share files
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("gb-emulator-{}-{}", std::process::id(), name))
+}
@@ -37,6 +37,10 @@ impl Timer {
         }
     }
 
+    pub fn reset_div(&mut self) {
+        self.div = 0;
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
             0xFF04 => (self.div >> 8) as u8,
@@ -49,7 +53,7 @@ impl Timer {
 
     pub fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            0xFF04 => self.div = 0, // write to DIV resets it
+            0xFF04 => self.reset_div(), // write to DIV resets it
             0xFF05 => {
                 if !self.overflow {
                     self.tima = val
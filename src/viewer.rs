@@ -0,0 +1,151 @@
+use sdl2::{keyboard::Keycode, Sdl};
+
+use crate::ppu::Ppu;
+
+pub mod oam;
+pub mod tilemap;
+pub mod tiles;
+
+use self::{oam::OamViewer, tilemap::TileMapViewer, tiles::TileViewer};
+
+// debug windows showing the PPU state, refreshed every frame
+pub trait Viewer {
+    fn window_id(&self) -> u32;
+    fn update(&mut self, ppu: &Ppu);
+    // key pressed while the window has focus, may change the debug switches of the PPU
+    fn key_down(&mut self, _key: Keycode, _ppu: &mut Ppu) {}
+    // cursor position in window coordinates
+    fn mouse_motion(&mut self, _x: i32, _y: i32, _ppu: &Ppu) {}
+}
+
+// window coordinates to image coordinates, the window may have been resized
+fn to_image(window: (u32, u32), image: (usize, usize), x: i32, y: i32) -> Option<(usize, usize)> {
+    if x < 0 || y < 0 || x as u32 >= window.0 || y as u32 >= window.1 {
+        return None;
+    }
+    Some((
+        x as usize * image.0 / window.0 as usize,
+        y as usize * image.1 / window.1 as usize,
+    ))
+}
+
+// 3x5 glyphs, 3 bits per row with the left pixel in the upper bit
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => [0; 5],
+    }
+}
+
+// text into an RGB24 image, 4 pixels per character
+fn draw_text(pixels: &mut [u8], width: usize, x: usize, y: usize, text: &str, color: [u8; 3]) {
+    for (n, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    let i = ((y + row) * width + x + n * 4 + col) * 3;
+                    pixels[i..i + 3].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Viewers {
+    tiles: Option<TileViewer>,
+    tilemap: Option<TileMapViewer>,
+    oam: Option<OamViewer>,
+}
+
+impl Viewers {
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut dyn Viewer> {
+        let tiles = self.tiles.iter_mut().map(|v| v as &mut dyn Viewer);
+        let tilemap = self.tilemap.iter_mut().map(|v| v as &mut dyn Viewer);
+        let oam = self.oam.iter_mut().map(|v| v as &mut dyn Viewer);
+        tiles.chain(tilemap).chain(oam)
+    }
+
+    fn owns(&mut self, window_id: u32) -> bool {
+        self.iter_mut().any(|v| v.window_id() == window_id)
+    }
+
+    pub fn toggle_tiles(&mut self, sdl: &Sdl, ppu: &Ppu) {
+        self.tiles = match self.tiles.take() {
+            Some(_) => None,
+            None => Some(TileViewer::new(sdl, ppu)),
+        };
+    }
+
+    pub fn toggle_tilemap(&mut self, sdl: &Sdl, ppu: &Ppu) {
+        self.tilemap = match self.tilemap.take() {
+            Some(_) => None,
+            None => Some(TileMapViewer::new(sdl, ppu)),
+        };
+    }
+
+    pub fn toggle_oam(&mut self, sdl: &Sdl, ppu: &Ppu) {
+        self.oam = match self.oam.take() {
+            Some(_) => None,
+            None => Some(OamViewer::new(sdl, ppu)),
+        };
+    }
+
+    pub fn update(&mut self, ppu: &Ppu) {
+        for viewer in self.iter_mut() {
+            viewer.update(ppu);
+        }
+    }
+
+    // false if the key was not pressed in a debug window
+    pub fn key_down(&mut self, window_id: u32, key: Keycode, ppu: &mut Ppu) -> bool {
+        if !self.owns(window_id) {
+            return false;
+        }
+        for viewer in self.iter_mut().filter(|v| v.window_id() == window_id) {
+            viewer.key_down(key, ppu);
+        }
+        true
+    }
+
+    pub fn mouse_motion(&mut self, window_id: u32, x: i32, y: i32, ppu: &Ppu) {
+        for viewer in self.iter_mut().filter(|v| v.window_id() == window_id) {
+            viewer.mouse_motion(x, y, ppu);
+        }
+    }
+
+    // false if the window is not a debug window
+    pub fn close(&mut self, window_id: u32) -> bool {
+        fn close<T: Viewer>(viewer: &mut Option<T>, window_id: u32) -> bool {
+            let owns = viewer.as_ref().is_some_and(|v| v.window_id() == window_id);
+            if owns {
+                *viewer = None;
+            }
+            owns
+        }
+        close(&mut self.tiles, window_id)
+            || close(&mut self.tilemap, window_id)
+            || close(&mut self.oam, window_id)
+    }
+}
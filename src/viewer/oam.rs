@@ -0,0 +1,326 @@
+use sdl2::{keyboard::Keycode, Sdl};
+
+use super::{draw_text, to_image, Viewer};
+use crate::{
+    lcd::Lcd,
+    ppu::{
+        Ppu, Sprite, ViewPalette, CGB_PALETTE, LCD_HEIGHT, OBJ2BG_PRIORITY, PALETTE,
+        TILE_VRAM_BANK, X_FLIP, Y_FLIP,
+    },
+};
+
+const SPRITES: usize = 40;
+const ENTRY_WIDTH: usize = 128;
+const ENTRY_HEIGHT: usize = 18; // 8x16 preview and the border
+const ENTRY_ROWS: usize = 20;
+const LIST_WIDTH: usize = ENTRY_WIDTH * SPRITES / ENTRY_ROWS;
+const CHART_X: usize = LIST_WIDTH + 8;
+const CHART_COLUMN: usize = 2; // width of a sprite column
+const WIDTH: usize = CHART_X + SPRITES * CHART_COLUMN;
+const HEIGHT: usize = ENTRY_HEIGHT * ENTRY_ROWS;
+const SCALE: u32 = 2;
+
+const BACKGROUND: [u8; 3] = [0x20; 3];
+const TEXT: [u8; 3] = [0xE0; 3];
+const HIDDEN_TEXT: [u8; 3] = [0x70; 3];
+const IDLE: [u8; 3] = [0x40; 3];
+const SELECTED: [u8; 3] = [0x30, 0xC0, 0x30];
+const DROPPED: [u8; 3] = [0xE0, 0x30, 0x30];
+const TRANSPARENT: [[u8; 3]; 2] = [[0x60; 3], [0x80; 3]];
+
+#[derive(Clone, Copy)]
+enum Hover {
+    Sprite(usize),
+    Line(usize, usize), // line, OAM index
+}
+
+// all sprites in OAM with a preview, and which sprites the OAM scan selected (green)
+// or dropped by the 10 sprites limit (red) on each line
+pub struct OamViewer {
+    window: Lcd,
+    hover: Option<Hover>,
+}
+
+fn palette(ppu: &Ppu, sprite: &Sprite) -> ViewPalette {
+    if ppu.is_cgb() {
+        ViewPalette::CgbObj(sprite.flags & CGB_PALETTE)
+    } else if sprite.flags & PALETTE == 0 {
+        ViewPalette::Obp0
+    } else {
+        ViewPalette::Obp1
+    }
+}
+
+// OAM indices of a bitmask
+fn indices(mask: u64) -> impl Iterator<Item = usize> {
+    (0..SPRITES).filter(move |i| mask & (1 << i) != 0)
+}
+
+// union of the per line masks over the frame
+fn frame_masks(ppu: &Ppu) -> (u64, u64) {
+    (0..LCD_HEIGHT)
+        .map(|ly| ppu.oam_line(ly))
+        .fold((0, 0), |(s, d), (selected, dropped)| {
+            (s | selected, d | dropped)
+        })
+}
+
+fn entry_origin(i: usize) -> (usize, usize) {
+    (i / ENTRY_ROWS * ENTRY_WIDTH, i % ENTRY_ROWS * ENTRY_HEIGHT)
+}
+
+fn entry_text(ppu: &Ppu, i: usize, sprite: &Sprite) -> String {
+    let flag = |mask: u8, c: char| if sprite.flags & mask != 0 { c } else { '-' };
+    let mut text = format!(
+        "{:02} X{:4} Y{:4} T{:02X} {}{}{} ",
+        i,
+        sprite.x as i16 - 8,
+        sprite.y as i16 - 16,
+        sprite.tile_idx,
+        flag(OBJ2BG_PRIORITY, 'P'),
+        flag(X_FLIP, 'X'),
+        flag(Y_FLIP, 'Y')
+    );
+    if ppu.is_cgb() {
+        text.push_str(&format!(
+            "O{} B{}",
+            sprite.flags & CGB_PALETTE,
+            (sprite.flags & TILE_VRAM_BANK != 0) as u8
+        ));
+    } else {
+        text.push_str(&format!("O{}", (sprite.flags & PALETTE != 0) as u8));
+    }
+    text
+}
+
+fn fill(pixels: &mut [u8], x: usize, y: usize, w: usize, h: usize, color: [u8; 3]) {
+    for py in y..y + h {
+        for px in x..x + w {
+            let i = (py * WIDTH + px) * 3;
+            pixels[i..i + 3].copy_from_slice(&color);
+        }
+    }
+}
+
+fn draw_preview(pixels: &mut [u8], ppu: &Ppu, x: usize, y: usize, sprite: &Sprite) {
+    let height = ppu.sprite_height();
+    let bank = (ppu.is_cgb() && sprite.flags & TILE_VRAM_BANK != 0) as usize;
+    let palette = palette(ppu, sprite);
+    for row in 0..height {
+        let tile_row = if sprite.flags & Y_FLIP == 0 {
+            row
+        } else {
+            height - 1 - row
+        };
+        let tile_idx = if height == 16 {
+            (sprite.tile_idx & 0xFE) as usize + (tile_row >= 8) as usize
+        } else {
+            sprite.tile_idx as usize
+        };
+        for col in 0..8 {
+            let tile_col = if sprite.flags & X_FLIP == 0 {
+                col
+            } else {
+                7 - col
+            };
+            let pixel = ppu.get_pixel_from_tile(bank, tile_idx, tile_row & 7, tile_col);
+            let color = if pixel == 0 {
+                TRANSPARENT[((row ^ col) & 1) as usize]
+            } else {
+                ppu.view_color(palette, pixel)
+            };
+            fill(pixels, x + col as usize, y + row as usize, 1, 1, color);
+        }
+    }
+}
+
+pub fn render(ppu: &Ppu) -> Box<[u8]> {
+    let mut pixels = vec![0; WIDTH * HEIGHT * 3];
+    fill(&mut pixels, 0, 0, WIDTH, HEIGHT, BACKGROUND);
+    let (selected, dropped) = frame_masks(ppu);
+    for (i, sprite) in ppu.sprites().iter().enumerate() {
+        let (x, y) = entry_origin(i);
+        let border = if dropped & (1 << i) != 0 {
+            DROPPED
+        } else if selected & (1 << i) != 0 {
+            SELECTED
+        } else {
+            IDLE
+        };
+        fill(&mut pixels, x, y, ENTRY_WIDTH - 1, ENTRY_HEIGHT - 1, border);
+        fill(
+            &mut pixels,
+            x + 1,
+            y + 1,
+            ENTRY_WIDTH - 3,
+            ENTRY_HEIGHT - 3,
+            BACKGROUND,
+        );
+        draw_preview(&mut pixels, ppu, x + 1, y + 1, sprite);
+        let text = entry_text(ppu, i, sprite);
+        let color = if ppu.layers.is_sprite_visible(i) {
+            TEXT
+        } else {
+            HIDDEN_TEXT
+        };
+        draw_text(&mut pixels, WIDTH, x + 12, y + 6, &text, color);
+    }
+    for ly in 0..LCD_HEIGHT {
+        let (selected, dropped) = ppu.oam_line(ly);
+        for (mask, color) in [(selected, SELECTED), (dropped, DROPPED)] {
+            for i in indices(mask) {
+                fill(
+                    &mut pixels,
+                    CHART_X + i * CHART_COLUMN,
+                    ly,
+                    CHART_COLUMN,
+                    1,
+                    color,
+                );
+            }
+        }
+    }
+    pixels.into()
+}
+
+impl OamViewer {
+    pub fn new(sdl: &Sdl, ppu: &Ppu) -> Self {
+        let mut ret = Self {
+            window: Lcd::with_title(sdl, "OAM", SCALE, WIDTH, HEIGHT),
+            hover: None,
+        };
+        ret.update(ppu);
+        ret
+    }
+
+    fn update_title(&mut self, ppu: &Ppu) {
+        let list = |mask: u64| {
+            indices(mask)
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let title = match self.hover {
+            Some(Hover::Sprite(i)) => {
+                let sprite = ppu.sprites()[i];
+                let lines = |dropped: bool| {
+                    (0..LCD_HEIGHT)
+                        .filter(|&ly| {
+                            let masks = ppu.oam_line(ly);
+                            let mask = if dropped { masks.1 } else { masks.0 };
+                            mask & (1 << i) != 0
+                        })
+                        .count()
+                };
+                format!(
+                    "OAM - sprite {} at FE{:02X}: ({}, {}), tile ${:02X}, flags {:02X} {:?}, selected on {} lines, dropped on {} lines, H: hide/show",
+                    i,
+                    i * 4,
+                    sprite.x as i16 - 8,
+                    sprite.y as i16 - 16,
+                    sprite.tile_idx,
+                    sprite.flags,
+                    palette(ppu, &sprite),
+                    lines(false),
+                    lines(true)
+                )
+            }
+            Some(Hover::Line(ly, i)) => {
+                let (selected, dropped) = ppu.oam_line(ly);
+                format!(
+                    "OAM - line {} (sprite {}): selected [{}], dropped [{}]",
+                    ly,
+                    i,
+                    list(selected),
+                    list(dropped)
+                )
+            }
+            None => "OAM - green: selected by the OAM scan, red: dropped by the 10 sprites limit"
+                .to_string(),
+        };
+        self.window.set_title(&title);
+    }
+}
+
+impl Viewer for OamViewer {
+    fn window_id(&self) -> u32 {
+        self.window.window_id()
+    }
+
+    fn update(&mut self, ppu: &Ppu) {
+        self.window.draw(&render(ppu), WIDTH, HEIGHT);
+        self.update_title(ppu);
+    }
+
+    fn key_down(&mut self, key: Keycode, ppu: &mut Ppu) {
+        if let (Keycode::H, Some(Hover::Sprite(i) | Hover::Line(_, i))) = (key, self.hover) {
+            let visible = ppu.layers.is_sprite_visible(i);
+            ppu.layers.set_sprite_visible(i, !visible);
+            self.update(ppu);
+        }
+    }
+
+    fn mouse_motion(&mut self, x: i32, y: i32, ppu: &Ppu) {
+        self.hover =
+            to_image(self.window.window_size(), (WIDTH, HEIGHT), x, y).and_then(|(x, y)| {
+                if x < LIST_WIDTH {
+                    Some(Hover::Sprite(
+                        x / ENTRY_WIDTH * ENTRY_ROWS + y / ENTRY_HEIGHT,
+                    ))
+                } else if x >= CHART_X && y < LCD_HEIGHT {
+                    Some(Hover::Line(y, (x - CHART_X) / CHART_COLUMN))
+                } else {
+                    None
+                }
+            });
+        self.update_title(ppu);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::interrupt::Interrupts;
+
+    fn pixel(pixels: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let i = (y * WIDTH + x) * 3;
+        pixels[i..i + 3].try_into().unwrap()
+    }
+
+    // top left corner of the border
+    fn border(pixels: &[u8], i: usize) -> [u8; 3] {
+        let (x, y) = entry_origin(i);
+        pixel(pixels, x, y)
+    }
+
+    #[test]
+    fn dropped_sprite() {
+        let mut ppu = Ppu::new(None, false);
+        // 11 sprites on lines 0-7
+        for i in 0..11 {
+            ppu.write(0xFE00 + i * 4, 16);
+            ppu.write(0xFE00 + i * 4 + 1, 8 + i as u8 * 8);
+        }
+        ppu.write(0xFF40, 0x82);
+        let mut interrupts = Interrupts::default();
+        for _ in 0..70224 / 4 {
+            ppu.emulate_cycle(&mut interrupts, 4);
+        }
+        let pixels = render(&ppu);
+        assert_eq!(border(&pixels, 0), SELECTED);
+        assert_eq!(border(&pixels, 9), SELECTED);
+        assert_eq!(border(&pixels, 10), DROPPED);
+        assert_eq!(border(&pixels, 11), IDLE);
+        // chart: one row per line, one column per sprite
+        assert_eq!(pixel(&pixels, CHART_X, 0), SELECTED);
+        assert_eq!(pixel(&pixels, CHART_X + 10 * CHART_COLUMN, 7), DROPPED);
+        assert_eq!(pixel(&pixels, CHART_X + 10 * CHART_COLUMN, 8), BACKGROUND);
+
+        // turning the LCD off clears the lines
+        ppu.write(0xFF40, 0x00);
+        let pixels = render(&ppu);
+        assert_eq!(border(&pixels, 0), IDLE);
+        assert_eq!(border(&pixels, 10), IDLE);
+        assert_eq!(pixel(&pixels, CHART_X + 10 * CHART_COLUMN, 7), BACKGROUND);
+    }
+}
@@ -0,0 +1,226 @@
+use std::path::Path;
+
+use sdl2::{keyboard::Keycode, Sdl};
+
+use super::{to_image, Viewer};
+use crate::{
+    lcd::Lcd,
+    png,
+    ppu::{Ppu, ViewPalette, CGB_PALETTE, LCD_HEIGHT, LCD_WIDTH, TILE_VRAM_BANK, X_FLIP, Y_FLIP},
+};
+
+const MAP_SIZE: usize = 256; // 32 * 32 tiles
+const WIDTH: usize = MAP_SIZE * 2;
+const HEIGHT: usize = MAP_SIZE;
+const SCALE: u32 = 2;
+
+const VIEWPORT_COLOR: [u8; 3] = [0xFF, 0x00, 0x00];
+const WINDOW_COLOR: [u8; 3] = [0x00, 0x60, 0xFF];
+
+// BG tile maps, 0x9800 to the left of 0x9C00
+pub struct TileMapViewer {
+    window: Lcd,
+    hover: Option<(bool, u8, u8)>, // tile map, row, col
+}
+
+// color of a map pixel with the current addressing mode, BGP or CGB attributes
+fn map_color(ppu: &Ppu, tile_map: bool, x: usize, y: usize) -> [u8; 3] {
+    let (row, col) = ((y >> 3) as u8, (x >> 3) as u8);
+    let tile = ppu.get_tile_idx_from_tile_map(tile_map, row, col);
+    let attr = ppu.get_tile_attr_from_tile_map(tile_map, row, col);
+    let (mut py, mut px) = ((y & 7) as u8, (x & 7) as u8);
+    if attr & Y_FLIP != 0 {
+        py = 7 - py;
+    }
+    if attr & X_FLIP != 0 {
+        px = 7 - px;
+    }
+    let bank = (attr & TILE_VRAM_BANK != 0) as usize;
+    let pixel = ppu.get_pixel_from_tile(bank, tile, py, px);
+    let palette = if ppu.is_cgb() {
+        ViewPalette::CgbBg(attr & CGB_PALETTE)
+    } else {
+        ViewPalette::Bgp
+    };
+    ppu.view_color(palette, pixel)
+}
+
+// outline of a rectangle wrapping around the edges of a map
+fn draw_rect(
+    pixels: &mut [u8],
+    tile_map: bool,
+    x: usize,
+    y: usize,
+    w: usize,
+    h: usize,
+    color: [u8; 3],
+) {
+    let mut set = |dx: usize, dy: usize| {
+        let px = (x + dx) % MAP_SIZE + tile_map as usize * MAP_SIZE;
+        let py = (y + dy) % MAP_SIZE;
+        let i = (py * WIDTH + px) * 3;
+        pixels[i..i + 3].copy_from_slice(&color);
+    };
+    for dx in 0..w {
+        set(dx, 0);
+        set(dx, h - 1);
+    }
+    for dy in 0..h {
+        set(0, dy);
+        set(w - 1, dy);
+    }
+}
+
+// RGB24 image of both tile maps with the screen and the window drawn on top
+pub fn render(ppu: &Ppu) -> Box<[u8]> {
+    let mut pixels = vec![0; WIDTH * HEIGHT * 3];
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let i = (y * WIDTH + x) * 3;
+            let color = map_color(ppu, x >= MAP_SIZE, x % MAP_SIZE, y);
+            pixels[i..i + 3].copy_from_slice(&color);
+        }
+    }
+    let (bg_map, window_map) = ppu.tile_maps();
+    let (scx, scy) = ppu.scroll();
+    draw_rect(
+        &mut pixels,
+        bg_map,
+        scx as usize,
+        scy as usize,
+        LCD_WIDTH,
+        LCD_HEIGHT,
+        VIEWPORT_COLOR,
+    );
+    if let Some((wx, wy)) = ppu.window_position() {
+        // the window starts at the origin of its map, at (WX - 7, WY) on the screen
+        let (left, top) = ((wx as usize).saturating_sub(7), wy as usize);
+        if left < LCD_WIDTH && top < LCD_HEIGHT {
+            let (w, h) = (LCD_WIDTH - left, LCD_HEIGHT - top);
+            draw_rect(&mut pixels, window_map, 0, 0, w, h, WINDOW_COLOR);
+            // origin marker inside the viewport
+            let (x, y) = (scx as usize + left, scy as usize + top);
+            draw_rect(&mut pixels, bg_map, x, y, 3, 3, WINDOW_COLOR);
+        }
+    }
+    pixels.into()
+}
+
+pub fn save(path: &Path, ppu: &Ppu) -> std::io::Result<()> {
+    png::save(path, &render(ppu), WIDTH, HEIGHT)
+}
+
+impl TileMapViewer {
+    pub fn new(sdl: &Sdl, ppu: &Ppu) -> Self {
+        let mut ret = Self {
+            window: Lcd::with_title(sdl, "tile maps", SCALE, WIDTH, HEIGHT),
+            hover: None,
+        };
+        ret.update(ppu);
+        ret
+    }
+
+    fn update_title(&mut self, ppu: &Ppu) {
+        let (scx, scy) = ppu.scroll();
+        let mut title = format!("tile maps - SCX {} SCY {}", scx, scy);
+        if let Some((wx, wy)) = ppu.window_position() {
+            title.push_str(&format!(", WX {} WY {}", wx, wy));
+        }
+        title.push_str(", P: save tilemap.png");
+        if let Some((tile_map, row, col)) = self.hover {
+            let addr = 0x9800 | (tile_map as usize) << 10 | (row as usize) << 5 | col as usize;
+            title.push_str(&format!(
+                " - ({}, {}) at {:04X}: tile ${:03X}",
+                col,
+                row,
+                addr,
+                ppu.get_tile_idx_from_tile_map(tile_map, row, col)
+            ));
+            if ppu.is_cgb() {
+                title.push_str(&format!(
+                    " attr {:02X}",
+                    ppu.get_tile_attr_from_tile_map(tile_map, row, col)
+                ));
+            }
+        }
+        self.window.set_title(&title);
+    }
+}
+
+impl Viewer for TileMapViewer {
+    fn window_id(&self) -> u32 {
+        self.window.window_id()
+    }
+
+    fn update(&mut self, ppu: &Ppu) {
+        self.window.draw(&render(ppu), WIDTH, HEIGHT);
+        self.update_title(ppu);
+    }
+
+    fn key_down(&mut self, key: Keycode, ppu: &mut Ppu) {
+        if key == Keycode::P {
+            match save(Path::new("tilemap.png"), ppu) {
+                Ok(()) => println!("saved tilemap.png"),
+                Err(e) => eprintln!("failed to save tilemap.png: {}", e),
+            }
+        }
+    }
+
+    fn mouse_motion(&mut self, x: i32, y: i32, ppu: &Ppu) {
+        self.hover = to_image(self.window.window_size(), (WIDTH, HEIGHT), x, y)
+            .map(|(x, y)| (x >= MAP_SIZE, (y / 8) as u8, (x % MAP_SIZE / 8) as u8));
+        self.update_title(ppu);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(pixels: &[u8], x: usize, y: usize) -> [u8; 3] {
+        let i = (y * WIDTH + x) * 3;
+        pixels[i..i + 3].try_into().unwrap()
+    }
+
+    #[test]
+    fn render_tile_map() {
+        let mut ppu = Ppu::new(None, false);
+        ppu.write(0xFF47, 0xE4);
+        // LCD off, tile data at 0x8000
+        ppu.write(0xFF40, 0x10);
+        // tile 1 color 3 at (1, 2) of the 0x9C00 map, right of the 0x9800 map
+        for addr in 0x8010..0x8020 {
+            ppu.write(addr, 0xFF);
+        }
+        ppu.write(0x9C00 + 2 * 32 + 1, 1);
+        let pixels = render(&ppu);
+        assert_eq!(pixel(&pixels, MAP_SIZE + 8, 16), [0x00; 3]);
+        assert_eq!(pixel(&pixels, MAP_SIZE + 15, 23), [0x00; 3]);
+        assert_eq!(pixel(&pixels, MAP_SIZE + 16, 16), [0xFF; 3]);
+        assert_eq!(pixel(&pixels, 8, 16), [0xFF; 3]);
+    }
+
+    #[test]
+    fn viewport_wraps() {
+        let mut ppu = Ppu::new(None, false);
+        ppu.write(0xFF43, 200);
+        ppu.write(0xFF42, 180);
+        let pixels = render(&ppu);
+        // x 200 to 103 and y 180 to 67 across the edges of the 0x9800 map
+        for (x, y) in [
+            (200, 180),
+            (255, 180),
+            (0, 180),
+            (103, 180),
+            (103, 67),
+            (200, 0),
+        ] {
+            assert_eq!(pixel(&pixels, x, y), VIEWPORT_COLOR, "({}, {})", x, y);
+        }
+        for (x, y) in [(199, 180), (104, 180), (200, 179), (200, 68), (150, 100)] {
+            assert_ne!(pixel(&pixels, x, y), VIEWPORT_COLOR, "({}, {})", x, y);
+        }
+        // nothing drawn on the 0x9C00 map
+        assert!((0..HEIGHT).all(|y| pixel(&pixels, MAP_SIZE + 200, y) != VIEWPORT_COLOR));
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use sdl2::{keyboard::Keycode, Sdl};
+
+use super::{to_image, Viewer};
+use crate::{
+    lcd::Lcd,
+    png,
+    ppu::{Ppu, ViewPalette},
+};
+
+const TILES: usize = 384; // per VRAM bank
+const COLUMNS: usize = 16;
+const ROWS: usize = TILES / COLUMNS;
+const SCALE: u32 = 3;
+
+// VRAM tile data, 16 tiles per row, bank 1 to the right of bank 0 on CGB
+pub struct TileViewer {
+    window: Lcd,
+    palette: ViewPalette,
+    hover: Option<(usize, usize)>, // bank, tile index
+}
+
+fn banks(ppu: &Ppu) -> usize {
+    if ppu.is_cgb() {
+        2
+    } else {
+        1
+    }
+}
+
+fn image_size(ppu: &Ppu) -> (usize, usize) {
+    (COLUMNS * 8 * banks(ppu), ROWS * 8)
+}
+
+// RGB24 image of all tiles and its size
+pub fn render(ppu: &Ppu, palette: ViewPalette) -> (Box<[u8]>, usize, usize) {
+    let (width, height) = image_size(ppu);
+    let mut pixels = vec![0; width * height * 3];
+    for bank in 0..banks(ppu) {
+        for tile in 0..TILES {
+            let (tx, ty) = ((bank * COLUMNS + tile % COLUMNS) * 8, tile / COLUMNS * 8);
+            for row in 0..8 {
+                for col in 0..8 {
+                    let pixel = ppu.get_pixel_from_tile(bank, tile, row, col);
+                    let i = ((ty + row as usize) * width + tx + col as usize) * 3;
+                    pixels[i..i + 3].copy_from_slice(&ppu.view_color(palette, pixel));
+                }
+            }
+        }
+    }
+    (pixels.into(), width, height)
+}
+
+// headless export
+pub fn save(path: &Path, ppu: &Ppu, palette: ViewPalette) -> std::io::Result<()> {
+    let (pixels, width, height) = render(ppu, palette);
+    png::save(path, &pixels, width, height)
+}
+
+impl TileViewer {
+    pub fn new(sdl: &Sdl, ppu: &Ppu) -> Self {
+        let (width, height) = image_size(ppu);
+        let mut ret = Self {
+            window: Lcd::with_title(sdl, "tiles", SCALE, width, height),
+            palette: ViewPalette::Gray,
+            hover: None,
+        };
+        ret.update_title();
+        ret.update(ppu);
+        ret
+    }
+
+    fn update_title(&mut self) {
+        let mut title = format!(
+            "tiles - palette: {:?} (Tab), P: save tiles.png",
+            self.palette
+        );
+        if let Some((bank, tile)) = self.hover {
+            title.push_str(&format!(
+                " - tile ${:03X} at {}:{:04X}",
+                tile,
+                bank,
+                0x8000 + tile * 16
+            ));
+        }
+        self.window.set_title(&title);
+    }
+}
+
+impl Viewer for TileViewer {
+    fn window_id(&self) -> u32 {
+        self.window.window_id()
+    }
+
+    fn update(&mut self, ppu: &Ppu) {
+        let (pixels, width, height) = render(ppu, self.palette);
+        self.window.draw(&pixels, width, height);
+    }
+
+    fn key_down(&mut self, key: Keycode, ppu: &mut Ppu) {
+        match key {
+            Keycode::Tab => {
+                self.palette = self.palette.next(ppu.is_cgb());
+                self.update_title();
+                self.update(ppu);
+            }
+            Keycode::P => match save(Path::new("tiles.png"), ppu, self.palette) {
+                Ok(()) => println!("saved tiles.png"),
+                Err(e) => eprintln!("failed to save tiles.png: {}", e),
+            },
+            _ => {}
+        }
+    }
+
+    fn mouse_motion(&mut self, x: i32, y: i32, ppu: &Ppu) {
+        let size = image_size(ppu);
+        self.hover = to_image(self.window.window_size(), size, x, y).map(|(x, y)| {
+            let (col, row) = (x / 8, y / 8);
+            (col / COLUMNS, row * COLUMNS + col % COLUMNS)
+        });
+        self.update_title();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // row 2 of the tile: color 1 in the first pixel, 3 in the last one
+    fn write_tile_row(ppu: &mut Ppu, tile: u16) {
+        ppu.write(0x8000 + tile * 16 + 4, 0x81);
+        ppu.write(0x8000 + tile * 16 + 5, 0x01);
+    }
+
+    fn pixel(pixels: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * width + x) * 3;
+        pixels[i..i + 3].try_into().unwrap()
+    }
+
+    #[test]
+    fn render_tiles() {
+        let mut ppu = Ppu::new(None, false);
+        // tile 0x11: second row, second column of the image
+        write_tile_row(&mut ppu, 0x11);
+        let (pixels, width, height) = render(&ppu, ViewPalette::Gray);
+        assert_eq!((width, height), (128, 192));
+        assert_eq!(pixel(&pixels, width, 8, 10), [0xAA; 3]);
+        assert_eq!(pixel(&pixels, width, 9, 10), [0xFF; 3]);
+        assert_eq!(pixel(&pixels, width, 15, 10), [0x00; 3]);
+        assert_eq!(pixel(&pixels, width, 8, 9), [0xFF; 3]);
+    }
+
+    #[test]
+    fn render_cgb_banks() {
+        let mut ppu = Ppu::new(None, true);
+        ppu.write(0xFF4F, 1);
+        write_tile_row(&mut ppu, 0x01);
+        let (pixels, width, height) = render(&ppu, ViewPalette::Gray);
+        assert_eq!((width, height), (256, 192));
+        // bank 1 to the right of bank 0
+        assert_eq!(pixel(&pixels, width, 8, 2), [0xFF; 3]);
+        assert_eq!(pixel(&pixels, width, 128 + 8, 2), [0xAA; 3]);
+        assert_eq!(pixel(&pixels, width, 128 + 15, 2), [0x00; 3]);
+    }
+}
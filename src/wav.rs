@@ -0,0 +1,258 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+// RIFF WAVE writer, 16-bit PCM or 32-bit IEEE float
+// http://www-mmsp.ece.mcgill.ca/Documents/AudioFormats/WAVE/WAVE.html
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SampleFormat {
+    Int16,
+    Float32,
+}
+
+impl SampleFormat {
+    fn bytes(self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 2,
+            SampleFormat::Float32 => 4,
+        }
+    }
+}
+
+pub struct WavWriter {
+    file: BufWriter<File>,
+    format: SampleFormat,
+    channels: u16,
+    frames: u32,
+    fact_pos: Option<u64>, // sample count of the float format
+    data_pos: u64,
+}
+
+impl WavWriter {
+    pub fn create(
+        path: &Path,
+        format: SampleFormat,
+        channels: u16,
+        sample_rate: u32,
+    ) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * format.bytes();
+        let mut header = vec![];
+        header.extend_from_slice(b"RIFF\0\0\0\0WAVE");
+        let (tag, fmt_size): (u16, u32) = match format {
+            SampleFormat::Int16 => (1, 16),
+            SampleFormat::Float32 => (3, 18),
+        };
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&fmt_size.to_le_bytes());
+        header.extend_from_slice(&tag.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&(format.bytes() * 8).to_le_bytes());
+        let mut fact_pos = None;
+        if format == SampleFormat::Float32 {
+            // no format extension, the fact chunk is required for non-PCM formats
+            header.extend_from_slice(&0u16.to_le_bytes());
+            header.extend_from_slice(b"fact\x04\0\0\0");
+            fact_pos = Some(header.len() as u64);
+            header.extend_from_slice(&0u32.to_le_bytes());
+        }
+        header.extend_from_slice(b"data\0\0\0\0");
+        file.write_all(&header)?;
+        Ok(Self {
+            file,
+            format,
+            channels,
+            frames: 0,
+            fact_pos,
+            data_pos: header.len() as u64,
+        })
+    }
+
+    // samples: interleaved, -1.0 to 1.0
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            match self.format {
+                SampleFormat::Int16 => {
+                    let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                    self.file.write_all(&sample.to_le_bytes())?;
+                }
+                SampleFormat::Float32 => self.file.write_all(&sample.to_le_bytes())?,
+            }
+        }
+        self.frames += samples.len() as u32 / self.channels as u32;
+        Ok(())
+    }
+
+    // write the sizes in the header
+    pub fn finish(mut self) -> io::Result<()> {
+        let data_size = self.frames * (self.channels * self.format.bytes()) as u32;
+        let riff_size = self.data_pos as u32 - 8 + data_size;
+        let mut patches = vec![(4, riff_size), (self.data_pos - 4, data_size)];
+        if let Some(pos) = self.fact_pos {
+            patches.push((pos, self.frames));
+        }
+        for (pos, val) in patches {
+            self.file.seek(SeekFrom::Start(pos))?;
+            self.file.write_all(&val.to_le_bytes())?;
+        }
+        self.file.flush()
+    }
+}
+
+const STEM_NAMES: [&str; 4] = ["square1", "square2", "wave", "noise"];
+
+// the stereo mix, and optionally the output of each channel to its own mono file
+pub struct WavRecorder {
+    mix: WavWriter,
+    stems: Vec<WavWriter>,
+}
+
+impl WavRecorder {
+    // stems are written next to the mix, e.g. music.wav, music-square1.wav
+    pub fn start(
+        path: &Path,
+        format: SampleFormat,
+        stems: bool,
+        sample_rate: u32,
+    ) -> io::Result<Self> {
+        let mix = WavWriter::create(path, format, 2, sample_rate)?;
+        let stems = if stems {
+            STEM_NAMES
+                .iter()
+                .map(|name| WavWriter::create(&stem_path(path, name), format, 1, sample_rate))
+                .collect::<io::Result<_>>()?
+        } else {
+            vec![]
+        };
+        Ok(Self { mix, stems })
+    }
+
+    pub fn has_stems(&self) -> bool {
+        !self.stems.is_empty()
+    }
+
+    // mix: interleaved stereo, stems: the 4 channels interleaved
+    pub fn write(&mut self, mix: &[f32], stems: &[f32]) -> io::Result<()> {
+        self.mix.write(mix)?;
+        for (i, stem) in self.stems.iter_mut().enumerate() {
+            let samples: Vec<f32> = stems.iter().skip(i).step_by(4).copied().collect();
+            stem.write(&samples)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.mix.finish()?;
+        for stem in self.stems {
+            stem.finish()?;
+        }
+        Ok(())
+    }
+}
+
+fn stem_path(path: &Path, name: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}-{}.{}", stem, name, ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn u16_at(data: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn write(name: &str, format: SampleFormat, channels: u16, samples: &[f32]) -> Vec<u8> {
+        let path = temp_path(name);
+        let mut wav = WavWriter::create(&path, format, channels, 48000).unwrap();
+        wav.write(samples).unwrap();
+        wav.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data
+    }
+
+    #[test]
+    fn int16() {
+        let data = write(
+            "int16.wav",
+            SampleFormat::Int16,
+            2,
+            &[0.0, 1.0, -2.0, 0.5, 0.0, 0.0],
+        );
+        assert_eq!(data.len(), 44 + 12);
+        assert_eq!(data[..4], *b"RIFF");
+        assert_eq!(u32_at(&data, 4), 36 + 12);
+        assert_eq!(data[8..16], *b"WAVEfmt ");
+        assert_eq!(u32_at(&data, 16), 16);
+        assert_eq!(u16_at(&data, 20), 1); // PCM
+        assert_eq!(u16_at(&data, 22), 2);
+        assert_eq!(u32_at(&data, 24), 48000);
+        assert_eq!(u32_at(&data, 28), 48000 * 4);
+        assert_eq!(u16_at(&data, 32), 4);
+        assert_eq!(u16_at(&data, 34), 16);
+        assert_eq!(data[36..40], *b"data");
+        assert_eq!(u32_at(&data, 40), 12);
+        // clamped
+        assert_eq!(u16_at(&data, 46) as i16, i16::MAX);
+        assert_eq!(u16_at(&data, 48) as i16, -i16::MAX);
+    }
+
+    #[test]
+    fn float32() {
+        let data = write("float32.wav", SampleFormat::Float32, 1, &[0.25, -1.5, 1.0]);
+        assert_eq!(data.len(), 58 + 12);
+        assert_eq!(u32_at(&data, 4), 50 + 12);
+        assert_eq!(data[8..16], *b"WAVEfmt ");
+        assert_eq!(u32_at(&data, 16), 18);
+        assert_eq!(u16_at(&data, 20), 3); // IEEE float
+        assert_eq!(u16_at(&data, 22), 1);
+        assert_eq!(u32_at(&data, 28), 48000 * 4);
+        assert_eq!(u16_at(&data, 32), 4);
+        assert_eq!(u16_at(&data, 34), 32);
+        assert_eq!(u16_at(&data, 36), 0); // no extension
+
+        // sample frames
+        assert_eq!(data[38..42], *b"fact");
+        assert_eq!(u32_at(&data, 42), 4);
+        assert_eq!(u32_at(&data, 46), 3);
+        assert_eq!(data[50..54], *b"data");
+        assert_eq!(u32_at(&data, 54), 12);
+        // not clamped
+        assert_eq!(data[62..66], (-1.5f32).to_le_bytes());
+    }
+
+    #[test]
+    fn stems() {
+        let path = temp_path("stems.wav");
+        assert_eq!(stem_path(&path, "wave"), temp_path("stems-wave.wav"));
+        let mut recorder = WavRecorder::start(&path, SampleFormat::Float32, true, 48000).unwrap();
+        assert!(recorder.has_stems());
+        let stems: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        recorder.write(&[0.0; 4], &stems).unwrap();
+        recorder.finish().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for (i, name) in STEM_NAMES.iter().enumerate() {
+            let stem = stem_path(&path, name);
+            let data = std::fs::read(&stem).unwrap();
+            std::fs::remove_file(&stem).unwrap();
+            assert_eq!(u16_at(&data, 22), 1);
+            assert_eq!(u32_at(&data, 46), 2);
+            assert_eq!(data[58..62], (i as f32).to_le_bytes());
+            assert_eq!(data[62..66], ((i + 4) as f32).to_le_bytes());
+        }
+    }
+}
@@ -1,15 +1,39 @@
-pub struct WRam(Box<[u8; 0x2000]>); // 8 KiB
+pub struct WRam {
+    ram: Box<[u8; 0x8000]>, // 4 KiB * 8 banks (CGB), DMG uses bank 0 and 1
+    svbk: u8,               // bank mapped to 0xD000-0xDFFF (CGB)
+}
 
 impl WRam {
     pub fn new() -> Self {
-        Self(Box::new([0; 0x2000]))
+        Self {
+            ram: Box::new([0; 0x8000]),
+            svbk: 0,
+        }
+    }
+
+    fn index(&self, addr: u16) -> usize {
+        let addr = (addr as usize) & 0x1fff;
+        if addr < 0x1000 {
+            addr
+        } else {
+            // bank 0 selects bank 1
+            (self.svbk.max(1) as usize) << 12 | (addr & 0x0fff)
+        }
     }
 
     pub fn read(&self, addr: u16) -> u8 {
-        self.0[(addr as usize) & 0x1fff]
+        self.ram[self.index(addr)]
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
-        self.0[(addr as usize) & 0x1fff] = val;
+        self.ram[self.index(addr)] = val;
+    }
+
+    pub fn read_svbk(&self) -> u8 {
+        0xF8 | self.svbk
+    }
+
+    pub fn write_svbk(&mut self, val: u8) {
+        self.svbk = val & 0b111;
     }
 }